@@ -0,0 +1,183 @@
+//! Rich annotations, which add the meaning of values with well-known tags to
+//! the comments in annotated diagnostic notation, and type annotations, which
+//! label every leaf value with its CBOR type.
+
+use dcbor::prelude::*;
+
+/// Formats `seconds` since the Unix epoch as an ISO 8601 UTC timestamp, with
+/// fractional seconds only when present.
+pub fn format_date(seconds: f64) -> Option<String> {
+    if !seconds.is_finite() || seconds.abs() > 1e14 {
+        return None;
+    }
+    let mut whole = seconds.floor() as i64;
+    let mut micros = ((seconds - whole as f64) * 1e6).round() as i64;
+    if micros == 1_000_000 {
+        whole += 1;
+        micros = 0;
+    }
+    let (days, time) = (whole.div_euclid(86400), whole.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+    let mut result = format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", year, month, day, time / 3600, time / 60 % 60, time % 60);
+    if micros > 0 {
+        result.push_str(format!(".{:06}", micros).trim_end_matches('0'));
+    }
+    result.push('Z');
+    Some(result)
+}
+
+/// Converts days since 1970-01-01 to a proleptic Gregorian (year, month, day).
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Adds the timestamp to the `/ date /` comment following each `1(N)` in
+/// annotated diagnostic notation, skipping over text strings.
+pub fn enrich(diag: &str) -> String {
+    let mut result = String::new();
+    for (index, line) in diag.split('\n').enumerate() {
+        if index > 0 {
+            result.push('\n');
+        }
+        let mut date = None;
+        let mut rest = line;
+        while let Some(c) = rest.chars().next() {
+            if c == '"' {
+                let len = string_len(rest);
+                result.push_str(&rest[..len]);
+                rest = &rest[len..];
+            } else if let Some(after) = rest.strip_prefix("/ date /") {
+                match date.take() {
+                    Some(date) => result.push_str(&format!("/ date: {} /", date)),
+                    None => result.push_str("/ date /"),
+                }
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("1(").filter(|_| !result.ends_with(|c: char| c.is_ascii_alphanumeric())) {
+                if let Some(end) = after.find(')') {
+                    if let Ok(seconds) = after[..end].parse::<f64>() {
+                        date = format_date(seconds);
+                    }
+                }
+                result.push_str("1(");
+                rest = after;
+            } else {
+                result.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    result
+}
+
+/// Replaces each `1(N)` in diagnostic notation with `T"<timestamp>"`, dropping
+/// the `/ date /` comment that follows it and skipping over text strings.
+pub fn compact_dates(diag: &str) -> String {
+    let mut result = String::new();
+    let mut rest = diag;
+    while let Some(c) = rest.chars().next() {
+        if c == '"' {
+            let len = string_len(rest);
+            result.push_str(&rest[..len]);
+            rest = &rest[len..];
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("1(").filter(|_| !result.ends_with(|c: char| c.is_ascii_alphanumeric())) {
+            let date = after.split_once(')').and_then(|(seconds, after)| Some((format_date(seconds.parse().ok()?)?, after)));
+            if let Some((date, after)) = date {
+                result.push_str(&format!("T\"{}\"", date));
+                let comma = after.starts_with(',');
+                let tail = after.strip_prefix(',').unwrap_or(after);
+                rest = match tail.trim_start_matches(' ').strip_prefix("/ date /") {
+                    Some(tail) => {
+                        if comma {
+                            result.push(',');
+                        }
+                        tail
+                    },
+                    None => after,
+                };
+                continue;
+            }
+        }
+        result.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    result
+}
+
+/// Returns the length of the quoted string at the start of `text`, including
+/// its quotes.
+fn string_len(text: &str) -> usize {
+    let mut escaped = false;
+    for (index, c) in text.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            return index + 1;
+        }
+    }
+    text.len()
+}
+
+/// The short name of the CBOR type of `cbor`, as used in type annotations.
+fn type_name(cbor: &CBOR) -> &'static str {
+    match cbor.as_case() {
+        CBORCase::Unsigned(_) => "uint",
+        CBORCase::Negative(_) => "nint",
+        CBORCase::ByteString(_) => "bstr",
+        CBORCase::Text(_) => "text",
+        CBORCase::Array(_) => "array",
+        CBORCase::Map(_) => "map",
+        CBORCase::Tagged(_, _) => "tag",
+        CBORCase::Simple(Simple::True | Simple::False) => "bool",
+        CBORCase::Simple(Simple::Null) => "null",
+        CBORCase::Simple(Simple::Float(_)) => "float",
+    }
+}
+
+/// Renders `cbor` as multiline diagnostic notation with a `/ type /` comment
+/// after every leaf value (including empty arrays and maps). Tags wrap their
+/// content on the same line.
+pub fn types(cbor: &CBOR) -> String {
+    let mut lines = vec!();
+    typed_lines(cbor, "", "", 0, &mut lines);
+    lines.join("\n")
+}
+
+/// Appends the lines for `cbor` at `level` to `lines`, with `prefix` (a map key
+/// or opening tags) before it and `suffix` (closing parentheses and a comma)
+/// after it.
+fn typed_lines(cbor: &CBOR, prefix: &str, suffix: &str, level: usize, lines: &mut Vec<String>) {
+    let indent = "    ".repeat(level);
+    match cbor.as_case() {
+        CBORCase::Tagged(tag, item) => {
+            typed_lines(item, &format!("{}{}(", prefix, tag.value()), &format!("){}", suffix), level, lines);
+        },
+        CBORCase::Array(array) if !array.is_empty() => {
+            lines.push(format!("{}{}[", indent, prefix));
+            for (index, item) in array.iter().enumerate() {
+                typed_lines(item, "", if index + 1 < array.len() { "," } else { "" }, level + 1, lines);
+            }
+            lines.push(format!("{}]{}", indent, suffix));
+        },
+        CBORCase::Map(map) if !map.is_empty() => {
+            lines.push(format!("{}{}{{", indent, prefix));
+            for (index, (key, value)) in map.iter().enumerate() {
+                typed_lines(value, &format!("{}: ", key), if index + 1 < map.len() { "," } else { "" }, level + 1, lines);
+            }
+            lines.push(format!("{}}}{}", indent, suffix));
+        },
+        _ => lines.push(format!("{}{}{}{}   / {} /", indent, prefix, cbor, suffix, type_name(cbor))),
+    }
+}
@@ -0,0 +1,65 @@
+use std::io::{Read, Write};
+
+use anyhow::{Result, bail};
+use clap::Args;
+use dcbor::prelude::*;
+
+use crate::{cmd::{Exec, IoArgs}, read_input, FormatOptions, InputFormat};
+
+/// Add elements to the end (or start) of an array
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    #[command(flatten)]
+    io: IoArgs,
+
+    /// An element to add, given in the --value-in format. May be repeated; the elements are added in the order given
+    #[arg(long = "value", value_name = "VALUE", required = true)]
+    values: Vec<String>,
+
+    /// Add the elements to the start of the array instead of the end
+    #[arg(long, default_value_t = false)]
+    prepend: bool,
+
+    /// The format of the --value elements
+    #[arg(long, value_enum, default_value_t = InputFormat::Hex)]
+    value_in: InputFormat,
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, reader: &mut dyn Read, writer: &mut dyn Write, _errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        let values = self.values.iter()
+            .map(|value| read_input(self.value_in, Some(value), &mut std::io::empty(), &options.json_input, false))
+            .collect::<Result<Vec<CBOR>>>()?;
+        let cbor = self.io.input.read(reader, options)?;
+        let CBORCase::Array(array) = cbor.as_case() else {
+            bail!("append requires an array as input");
+        };
+        let elements: Vec<CBOR> = if self.prepend {
+            values.into_iter().chain(array.iter().cloned()).collect()
+        } else {
+            array.iter().cloned().chain(values).collect()
+        };
+        self.io.output.write(&CBOR::from(elements), writer, options)?;
+        Ok(())
+    }
+
+    fn writes_binary(&self) -> bool {
+        self.io.output.writes_binary()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::test::{run, test_diag};
+
+    #[test]
+    fn test_append() {
+        test_diag(&["append", "--compact", "--value", "03", "--value", "6161", "820102"], r#"[1, 2, 3, "a"]"#);
+        test_diag(&["append", "--compact", "--prepend", "--value", "03", "--value", "6161", "820102"], r#"[3, "a", 1, 2]"#);
+        test_diag(&["append", "--compact", "--value-in", "json", "--value", "[true]", "80"], "[[true]]");
+        let result = run(["dcbor", "append", "--value", "01", "a0"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "append requires an array as input");
+    }
+}
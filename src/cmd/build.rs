@@ -0,0 +1,196 @@
+use std::{fmt, io::{Read, Write}, path::PathBuf};
+
+use anyhow::{Result, anyhow};
+use clap::Args;
+use dcbor::prelude::*;
+use serde::{Deserialize, Deserializer, de::{self, MapAccess, Visitor}};
+use serde_json::Value;
+
+use crate::{cmd::{Exec, OutputArgs}, read_text, read_text_file, FormatOptions};
+
+/// Build a dCBOR value from a JSON spec of typed values
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    /// The spec file. If not provided, the spec is read from STDIN
+    #[arg(value_name = "SPEC")]
+    spec: Option<PathBuf>,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+const TYPES: &[&str] = &["uint", "int", "float", "text", "bytes", "bool", "null", "array", "map"];
+const KEYS: &[&str] = &["type", "value", "items", "fields", "tag"];
+
+/// A value in the spec, already converted to dCBOR. Problems are reported as
+/// deserialization errors so that serde_json adds their line and column.
+struct Node(CBOR);
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(NodeVisitor)
+    }
+}
+
+struct NodeVisitor;
+
+impl<'de> Visitor<'de> for NodeVisitor {
+    type Value = Node;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an object with a `type`")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Node, A::Error> {
+        let (mut kind, mut value, mut items, mut fields, mut tag) = (None, None, None, None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "type" => kind = Some(map.next_value::<String>()?),
+                "value" => value = Some(map.next_value::<Value>()?),
+                "items" => items = Some(map.next_value::<Vec<Node>>()?),
+                "fields" => fields = Some(map.next_value::<Fields>()?),
+                "tag" => tag = Some(map.next_value::<u64>()?),
+                _ => return Err(de::Error::unknown_field(&key, KEYS)),
+            }
+        }
+        let kind = kind.ok_or_else(|| de::Error::missing_field("type"))?;
+        let cbor = match kind.as_str() {
+            "array" => {
+                if value.is_some() || fields.is_some() {
+                    return Err(de::Error::custom("an array takes `items`, not `value` or `fields`"));
+                }
+                let items = items.ok_or_else(|| de::Error::missing_field("items"))?;
+                items.into_iter().map(|Node(cbor)| cbor).collect::<Vec<CBOR>>().into()
+            },
+            "map" => {
+                if value.is_some() || items.is_some() {
+                    return Err(de::Error::custom("a map takes `fields`, not `value` or `items`"));
+                }
+                fields.ok_or_else(|| de::Error::missing_field("fields"))?.0.into()
+            },
+            _ if !TYPES.contains(&kind.as_str()) => return Err(de::Error::unknown_variant(&kind, TYPES)),
+            _ => {
+                if items.is_some() || fields.is_some() {
+                    return Err(de::Error::custom(format!("a {} takes `value`, not `items` or `fields`", kind)));
+                }
+                scalar(&kind, value).map_err(de::Error::custom)?
+            },
+        };
+        Ok(Node(match tag {
+            Some(tag) => CBOR::to_tagged_value(tag, cbor),
+            None => cbor,
+        }))
+    }
+}
+
+/// Converts the `value` of a scalar node of type `kind`.
+fn scalar(kind: &str, value: Option<Value>) -> Result<CBOR, String> {
+    if kind == "null" {
+        return match value {
+            None | Some(Value::Null) => Ok(CBORCase::Simple(Simple::Null).into()),
+            Some(_) => Err("a null takes no `value`".to_string()),
+        };
+    }
+    let value = value.ok_or_else(|| format!("a {} requires a `value`", kind))?;
+    let cbor = match (kind, &value) {
+        ("uint", Value::Number(number)) => number.as_u64().map(CBOR::from),
+        ("int", Value::Number(number)) => number.as_u64().map(CBOR::from).or_else(|| number.as_i64().map(CBOR::from)),
+        ("float", Value::Number(number)) => number.as_f64().map(CBOR::from),
+        ("text", Value::String(text)) => Some(text.as_str().into()),
+        ("bytes", Value::String(text)) => {
+            Some(CBOR::to_byte_string(hex::decode(text).map_err(|error| format!("invalid hex in bytes value: {}", error))?))
+        },
+        ("bool", Value::Bool(value)) => Some((*value).into()),
+        _ => None,
+    };
+    cbor.ok_or_else(|| {
+        let expected = match kind {
+            "uint" => "a non-negative integer",
+            "int" => "an integer in the 64-bit range",
+            "float" => "a number",
+            "text" => "a string",
+            "bytes" => "a string of hex digits",
+            _ => "true or false",
+        };
+        format!("a {} requires {} as its `value`, not {}", kind, expected, value)
+    })
+}
+
+/// The named values of a map node, which become text keys.
+struct Fields(Map);
+
+impl<'de> Deserialize<'de> for Fields {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(FieldsVisitor)
+    }
+}
+
+struct FieldsVisitor;
+
+impl<'de> Visitor<'de> for FieldsVisitor {
+    type Value = Fields;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an object of named values")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Fields, A::Error> {
+        let mut fields = Map::new();
+        while let Some(name) = map.next_key::<String>()? {
+            if fields.contains_key(name.as_str()) {
+                return Err(de::Error::custom(format!("duplicate field `{}`", name)));
+            }
+            let Node(value) = map.next_value()?;
+            fields.insert(name.as_str(), value);
+        }
+        Ok(Fields(fields))
+    }
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, reader: &mut dyn Read, writer: &mut dyn Write, _errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        let spec = options.timings.time("read input", || match &self.spec {
+            Some(path) => read_text_file(path),
+            None => read_text(reader),
+        })?;
+        let Node(cbor) = serde_json::from_str(&spec).map_err(|error| anyhow!("Invalid build spec: {}", error))?;
+        self.output.write(&cbor, writer, options)
+    }
+
+    fn writes_binary(&self) -> bool {
+        self.output.writes_binary()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::test::{run, run_with_input};
+    use indoc::indoc;
+
+    #[test]
+    fn test_build() {
+        let spec = indoc! {r#"
+            {
+                "type": "map",
+                "fields": {
+                    "name": {"type": "text", "value": "Al"},
+                    "id": {"type": "uint", "value": 7},
+                    "at": {"type": "int", "value": 1614124800, "tag": 1},
+                    "key": {"type": "bytes", "value": "0102"},
+                    "list": {"type": "array", "items": [{"type": "float", "value": 1.5}, {"type": "bool", "value": true}, {"type": "null"}]}
+                }
+            }
+        "#};
+        let output = run_with_input(&["build", "--compact"], spec.as_bytes());
+        assert_eq!(output, "{\"at\": 1(1614124800), \"id\": 7, \"key\": h'0102', \"list\": [1.5, true, null], \"name\": \"Al\"}\n");
+
+        let result = run(["dcbor", "build"], &mut Cursor::new(b"{\"type\": \"array\",\n \"items\": [\n  {\"type\": \"uint\", \"value\": -1}\n]}".to_vec()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Invalid build spec: a uint requires a non-negative integer as its `value`, not -1 at line 3 column 31");
+        let result = run(["dcbor", "build"], &mut Cursor::new(b"{\"type\": \"map\",\n \"fields\": {\"a\": {\"type\": \"txt\"}}}".to_vec()), &mut Vec::new());
+        assert!(result.unwrap_err().to_string().starts_with("Invalid build spec: unknown variant `txt`, expected one of `uint`, "));
+        let result = run(["dcbor", "build"], &mut Cursor::new(b"{\"type\": \"map\", \"fields\": {}, \"tga\": 1}".to_vec()), &mut Vec::new());
+        assert!(result.unwrap_err().to_string().starts_with("Invalid build spec: unknown field `tga`, expected one of `type`, "));
+    }
+}
@@ -0,0 +1,297 @@
+use std::{collections::HashMap, fs, io::{BufRead, BufReader, Read, Write}, path::{Path, PathBuf}};
+
+use anyhow::{Result, anyhow, bail};
+use base64::{Engine as _, engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD as BASE64_URL}};
+use clap::Args;
+use dcbor::prelude::*;
+
+use crate::{cmd::Exec, decode_data, definite, json::{self, JsonInput}, pem, read_input_bytes, write_output_file, FormatOptions, InputFormat, Timings};
+
+/// Rewrite files as canonical dCBOR
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    /// The files to canonicalize. Each is rewritten in place unless --output-dir is given
+    #[arg(required = true, value_name = "PATH")]
+    paths: Vec<PathBuf>,
+
+    /// Write the canonicalized files to this directory, under their original file names, instead of in place
+    #[arg(long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+
+    /// The format of the files, which are written back in the same format. Detection is not supported
+    #[arg(short, long, value_enum, default_value_t = InputFormat::Bin)]
+    r#in: InputFormat,
+
+    /// Also print the size of each file before and after canonicalizing, and where it was written when that is not in place
+    #[arg(short, long, default_value_t = false)]
+    verbose: bool,
+
+    /// Stop at the first file that fails, rather than reporting it and continuing with the rest
+    #[arg(long, default_value_t = false)]
+    stop_on_error: bool,
+
+    /// The number of files to canonicalize concurrently. Results are still reported in the order the files were given
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    jobs: u64,
+
+    /// Report which files would change, without writing anything
+    #[arg(long, default_value_t = false, conflicts_with = "confirm")]
+    dry_run: bool,
+
+    /// Ask on STDERR before writing each file, reading the answer from STDIN
+    #[arg(long, default_value_t = false)]
+    confirm: bool,
+}
+
+/// The canonical form of a file, encoded in its format and ready to be
+/// written.
+struct Rewrite {
+    output_path: PathBuf,
+    canonical: Vec<u8>,
+    original_len: usize,
+    changed: bool,
+}
+
+impl Rewrite {
+    /// Whether writing is needed: the content changed, or it goes to a
+    /// different file.
+    fn needs_write(&self, path: &Path) -> bool {
+        self.changed || self.output_path != path
+    }
+}
+
+/// Asks on `errors` whether to write `path`, reading the answer from
+/// `answers`. Anything but `y` or `yes` declines, including the end of the
+/// input.
+fn confirm(path: &Path, answers: &mut dyn BufRead, errors: &mut dyn Write) -> Result<bool> {
+    write!(errors, "Write {}? [y/N] ", path.display())?;
+    errors.flush()?;
+    let mut answer = String::new();
+    answers.read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+impl CommandArgs {
+    /// Canonicalizes the file at `path` without writing it. The file is decoded
+    /// leniently: indefinite-length items, integers and lengths not in their
+    /// shortest form, unsorted maps, and floats not in dCBOR's form are all
+    /// repaired before the value is checked as dCBOR.
+    fn canonicalize(&self, path: &Path, timings: &Timings, json_input: &JsonInput) -> Result<Rewrite> {
+        let contents = timings.time("read input", || fs::read(path))?;
+        let data = read_input_bytes(self.r#in, contents.clone(), json_input)?;
+        let cbor = timings.time("decode", || decode_data(definite::to_canonical(&data)?, false))?;
+        let canonical = self.encode(&cbor, &contents)?;
+        let changed = contents != canonical;
+        Ok(Rewrite { output_path: self.output_path(path)?, canonical, original_len: contents.len(), changed })
+    }
+
+    /// Encodes `cbor` in the input format, keeping the label of a PEM block
+    /// from the `original` contents of the file.
+    fn encode(&self, cbor: &CBOR, original: &[u8]) -> Result<Vec<u8>> {
+        let data = cbor.to_cbor_data();
+        let text = match self.r#in {
+            InputFormat::Bin => return Ok(data),
+            InputFormat::Hex => hex::encode(data),
+            InputFormat::Base64 => BASE64.encode(data),
+            InputFormat::Base64url => BASE64_URL.encode(data),
+            InputFormat::Json => serde_json::to_string_pretty(&json::from_cbor(cbor)?)?,
+            InputFormat::Pem => {
+                let label = String::from_utf8_lossy(original).lines()
+                    .find_map(|line| line.trim().strip_prefix("-----BEGIN ")?.strip_suffix("-----").map(str::to_string))
+                    .unwrap_or_else(|| "CBOR".to_string());
+                pem::encode(&label, &data)
+            },
+            InputFormat::Detect => unreachable!("--in detect is rejected before any file is read"),
+        };
+        Ok(format!("{}\n", text).into_bytes())
+    }
+
+    /// The path to write the canonical form of the file at `path` to.
+    fn output_path(&self, path: &Path) -> Result<PathBuf> {
+        match &self.output_dir {
+            Some(dir) => Ok(dir.join(path.file_name().ok_or_else(|| anyhow!("{} has no file name", path.display()))?)),
+            None => Ok(path.to_path_buf()),
+        }
+    }
+
+    /// Fails if two of the files would be written to the same place in
+    /// --output-dir.
+    fn check_output_paths(&self) -> Result<()> {
+        if self.output_dir.is_none() {
+            return Ok(());
+        }
+        let mut sources = HashMap::new();
+        for path in &self.paths {
+            let output_path = self.output_path(path)?;
+            if let Some(other) = sources.insert(output_path.clone(), path) {
+                if other != path {
+                    bail!("{} and {} would both be written to {}", other.display(), path.display(), output_path.display());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Canonicalizes `paths` on up to --jobs threads, returning the results
+    /// indexed in the same order as `paths`.
+    fn canonicalize_all(&self, paths: &[PathBuf], timings: &Timings, json_input: &JsonInput) -> Vec<Result<Rewrite>> {
+        if self.jobs == 1 {
+            return paths.iter().map(|path| self.canonicalize(path, timings, json_input)).collect();
+        }
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = paths.iter()
+                .map(|path| scope.spawn(move || self.canonicalize(path, timings, json_input)))
+                .collect();
+            handles.into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|_| Err(anyhow!("canonicalization panicked"))))
+                .collect()
+        })
+    }
+
+    /// Writes `rewrite` unless this is a dry run or the user declines,
+    /// returning the status to report for `path`.
+    fn apply(&self, path: &Path, rewrite: &Rewrite, answers: &mut dyn BufRead, errors: &mut dyn Write) -> Result<&'static str> {
+        if self.dry_run {
+            return Ok(if rewrite.changed { "would change" } else { "unchanged" });
+        }
+        if rewrite.needs_write(path) {
+            if self.confirm && !confirm(&rewrite.output_path, answers, errors)? {
+                return Ok("skipped");
+            }
+            write_output_file(&rewrite.output_path, |file| Ok(file.write_all(&rewrite.canonical)?))?;
+        }
+        Ok(if rewrite.changed { "changed" } else { "unchanged" })
+    }
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, reader: &mut dyn Read, writer: &mut dyn Write, errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        if self.r#in == InputFormat::Detect {
+            bail!("canonicalize cannot write files back in a detected format; give the format with --in");
+        }
+        self.check_output_paths()?;
+        let mut answers = BufReader::new(reader);
+        let (mut processed, mut changed, mut failed, mut skipped) = (0, 0, 0, 0);
+        // Files are processed in batches of --jobs so that --stop-on-error
+        // still stops shortly after the first failure.
+        let results = self.paths.chunks(self.jobs as usize)
+            .flat_map(|batch| self.canonicalize_all(batch, &options.timings, &options.json_input).into_iter().zip(batch));
+        for (result, path) in results {
+            processed += 1;
+            match result.and_then(|rewrite| Ok((self.apply(path, &rewrite, &mut answers, errors)?, rewrite))) {
+                Ok((status, rewrite)) => {
+                    match status {
+                        "changed" | "would change" => changed += 1,
+                        "skipped" => skipped += 1,
+                        _ => {},
+                    }
+                    if !self.verbose {
+                        writeln!(writer, "{}: {}", path.display(), status)?;
+                    } else if rewrite.output_path == *path || status == "skipped" {
+                        writeln!(writer, "{}: {} ({} -> {} bytes)", path.display(), status, rewrite.original_len, rewrite.canonical.len())?;
+                    } else {
+                        writeln!(writer, "{}: {} ({} -> {} bytes), {} {}", path.display(), status, rewrite.original_len, rewrite.canonical.len(), if self.dry_run { "would write" } else { "wrote" }, rewrite.output_path.display())?;
+                    }
+                },
+                Err(error) if self.stop_on_error => bail!("{}: {}", path.display(), error),
+                Err(error) => {
+                    failed += 1;
+                    writeln!(writer, "{}: FAILED: {}", path.display(), error)?;
+                },
+            }
+        }
+        if self.dry_run {
+            writeln!(writer, "{} processed, {} would change, {} failed", processed, changed, failed)?;
+        } else if self.confirm {
+            writeln!(writer, "{} processed, {} changed, {} skipped, {} failed", processed, changed, skipped, failed)?;
+        } else {
+            writeln!(writer, "{} processed, {} changed, {} failed", processed, changed, failed)?;
+        }
+        if failed > 0 {
+            bail!("{} file(s) could not be canonicalized", failed);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::test::{run, run_with_input};
+
+    #[test]
+    fn test_canonicalize() {
+        let dir = std::env::temp_dir().join(format!("dcbor-canonicalize-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let canonical = dir.join("canonical.cbor");
+        let indefinite = dir.join("indefinite.cbor");
+        let invalid = dir.join("invalid.cbor");
+        std::fs::write(&canonical, [0x82, 0x01, 0x02]).unwrap();
+        std::fs::write(&indefinite, [0x9f, 0x01, 0x02, 0xff]).unwrap();
+        std::fs::write(&invalid, [0x82, 0x01]).unwrap();
+        let output = run_with_input(&["canonicalize", canonical.to_str().unwrap(), indefinite.to_str().unwrap()], &[]);
+        assert_eq!(output, format!("{}: unchanged\n{}: changed\n2 processed, 1 changed, 0 failed\n", canonical.display(), indefinite.display()));
+        assert_eq!(std::fs::read(&indefinite).unwrap(), [0x82, 0x01, 0x02]);
+
+        // Definite-length input that is not canonical is repaired too.
+        let noncanonical = dir.join("noncanonical.cbor");
+        std::fs::write(&noncanonical, hex::decode("a2616218016161fb3ff0000000000000").unwrap()).unwrap();
+        let output = run_with_input(&["canonicalize", noncanonical.to_str().unwrap()], &[]);
+        assert_eq!(output, format!("{}: changed\n1 processed, 1 changed, 0 failed\n", noncanonical.display()));
+        assert_eq!(hex::encode(std::fs::read(&noncanonical).unwrap()), "a2616101616201");
+
+        let mut output = Vec::new();
+        let result = run(["dcbor", "canonicalize", invalid.to_str().unwrap(), canonical.to_str().unwrap()], &mut Cursor::new(vec!()), &mut output);
+        assert_eq!(result.unwrap_err().to_string(), "1 file(s) could not be canonicalized");
+        assert!(String::from_utf8(output).unwrap().starts_with(&format!("{}: FAILED: ", invalid.display())));
+        let result = run(["dcbor", "canonicalize", "--stop-on-error", invalid.to_str().unwrap()], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert!(result.unwrap_err().to_string().starts_with(&format!("{}: ", invalid.display())));
+
+        let paths: Vec<_> = (0..5).map(|index| dir.join(format!("item{}.cbor", index))).collect();
+        for path in &paths {
+            std::fs::write(path, [0x9f, 0x01, 0x02, 0xff]).unwrap();
+        }
+        let mut args = vec!("canonicalize", "--jobs", "3");
+        args.extend(paths.iter().map(|path| path.to_str().unwrap()));
+        let expected: String = paths.iter().map(|path| format!("{}: changed\n", path.display())).collect();
+        assert_eq!(run_with_input(&args, &[]), format!("{}5 processed, 5 changed, 0 failed\n", expected));
+
+        let first = paths[0].to_str().unwrap();
+        std::fs::write(first, [0x9f, 0xff]).unwrap();
+        let output = run_with_input(&["canonicalize", "--dry-run", first, canonical.to_str().unwrap()], &[]);
+        assert_eq!(output, format!("{}: would change\n{}: unchanged\n2 processed, 1 would change, 0 failed\n", first, canonical.display()));
+        assert_eq!(std::fs::read(first).unwrap(), [0x9f, 0xff]);
+        let output = run_with_input(&["canonicalize", "--confirm", first, paths[1].to_str().unwrap()], b"n\n");
+        assert_eq!(output, format!("{}: skipped\n{}: unchanged\n2 processed, 0 changed, 1 skipped, 0 failed\n", first, paths[1].display()));
+        assert_eq!(std::fs::read(first).unwrap(), [0x9f, 0xff]);
+        run_with_input(&["canonicalize", "--confirm", first], b"y\n");
+        assert_eq!(std::fs::read(first).unwrap(), [0x80]);
+
+        // Text files are compared and written back in their own format.
+        let hex = dir.join("canonical.hex");
+        let indefinite_hex = dir.join("indefinite.hex");
+        std::fs::write(&hex, "820102\n").unwrap();
+        std::fs::write(&indefinite_hex, "9f0102ff").unwrap();
+        let output = run_with_input(&["canonicalize", "--in", "hex", hex.to_str().unwrap(), indefinite_hex.to_str().unwrap()], &[]);
+        assert_eq!(output, format!("{}: unchanged\n{}: changed\n2 processed, 1 changed, 0 failed\n", hex.display(), indefinite_hex.display()));
+        assert_eq!(std::fs::read_to_string(&hex).unwrap(), "820102\n");
+        assert_eq!(std::fs::read_to_string(&indefinite_hex).unwrap(), "820102\n");
+
+        std::fs::write(&indefinite, [0x9f, 0x01, 0x02, 0xff]).unwrap();
+        let out = dir.join("out");
+        std::fs::create_dir_all(&out).unwrap();
+        let output = run_with_input(&["canonicalize", "--verbose", "--output-dir", out.to_str().unwrap(), indefinite.to_str().unwrap()], &[]);
+        assert_eq!(output, format!("{}: changed (4 -> 3 bytes), wrote {}\n1 processed, 1 changed, 0 failed\n", indefinite.display(), out.join("indefinite.cbor").display()));
+        assert_eq!(std::fs::read(out.join("indefinite.cbor")).unwrap(), [0x82, 0x01, 0x02]);
+        let output = run_with_input(&["canonicalize", "--verbose", canonical.to_str().unwrap()], &[]);
+        assert_eq!(output, format!("{}: unchanged (3 -> 3 bytes)\n1 processed, 0 changed, 0 failed\n", canonical.display()));
+
+        let other = dir.join("other");
+        std::fs::create_dir_all(&other).unwrap();
+        std::fs::copy(&canonical, other.join("canonical.cbor")).unwrap();
+        let result = run(["dcbor", "canonicalize", "--output-dir", out.to_str().unwrap(), canonical.to_str().unwrap(), other.join("canonical.cbor").to_str().unwrap()], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), format!("{} and {} would both be written to {}", canonical.display(), other.join("canonical.cbor").display(), out.join("canonical.cbor").display()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,50 @@
+use std::io::{Read, Write};
+
+use anyhow::Result;
+use clap::Args;
+use dcbor::prelude::*;
+
+use crate::{cmd::{Exec, IoArgs}, FormatOptions};
+
+/// The tag for a COSE_Sign1 structure (RFC 9052).
+const COSE_SIGN1: u64 = 18;
+
+/// Wrap a dCBOR value as the payload of an unsigned COSE_Sign1 skeleton
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    #[command(flatten)]
+    io: IoArgs,
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, reader: &mut dyn Read, writer: &mut dyn Write, _errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        let payload = self.io.input.read(reader, options)?;
+        // [protected, unprotected, payload, signature], with the payload as
+        // an encoded byte string and nothing signed.
+        let skeleton = vec!(
+            CBOR::to_byte_string([]),
+            Map::new().into(),
+            CBOR::to_byte_string(payload.to_cbor_data()),
+            CBOR::to_byte_string([]),
+        );
+        let cbor = CBOR::to_tagged_value(COSE_SIGN1, skeleton);
+        self.io.output.write(&cbor, writer, options)?;
+        Ok(())
+    }
+
+    fn writes_binary(&self) -> bool {
+        self.io.output.writes_binary()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::test_diag;
+
+    #[test]
+    fn test_cose() {
+        test_diag(&["cose", "--compact", "a1616101"], "18([h'', {}, h'a1616101', h''])");
+        test_diag(&["cose", "--out", "hex", "--compact", "01"], "d28440a0410140");
+    }
+}
@@ -0,0 +1,928 @@
+use std::{fs::File, io::{self, BufRead, BufReader, Cursor, Read, Write}, path::PathBuf, str::FromStr};
+
+use anyhow::{Result, anyhow, bail};
+use clap::{Args, ValueEnum};
+use dcbor::prelude::*;
+use base64::{Engine as _, engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD as BASE64_URL}};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{
+    cmd::{Exec, IoArgs},
+    clean_hex, clean_text, decode_data, definite, floats::{self, FloatWidth}, format_output, json::JsonInput, pem, read_input_data, read_input_file, read_text, read_text_file, scan, walk,
+    FormatOptions, InputFormat, OutputFormat,
+};
+
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    #[command(flatten)]
+    io: IoArgs,
+
+    /// Read the input from a file instead of the command line or STDIN. Binary input is read as raw bytes; other formats are read as UTF-8 text
+    #[arg(long, value_name = "PATH", conflicts_with = "hex")]
+    input_file: Option<PathBuf>,
+
+    /// Read the input as a PEM block and decode its base64 body as binary dCBOR. Overrides the input format; the same as --in pem
+    #[arg(long, default_value_t = false)]
+    from_pem: bool,
+
+    /// Output the encoded dCBOR as a PEM block with the given label (e.g. CBOR). Overrides the output format
+    #[arg(long, value_name = "LABEL", conflicts_with = "pad_to")]
+    to_pem: Option<String>,
+
+    /// Echo the decoded input as compact diagnostic notation to STDERR before producing the output
+    #[arg(long, default_value_t = false)]
+    tee: bool,
+
+    /// Warn on STDERR about maps whose keys are all numeric text strings, which sort lexically ("1", "10", "2") rather than numerically
+    #[arg(long, default_value_t = false)]
+    warn_numeric_string_keys: bool,
+
+    /// Output a new array containing only the elements of the input array at the given indices or inclusive ranges (e.g. 0,2,4-6). Negative indices count from the end
+    #[arg(long, value_name = "INDICES", allow_hyphen_values = true)]
+    select: Option<String>,
+
+    /// Skip out-of-range indices given to --select instead of failing
+    #[arg(long, default_value_t = false)]
+    lenient: bool,
+
+    /// Fail if any map in the input has a key that is not a text string, as required for conversion to JSON
+    #[arg(long, default_value_t = false)]
+    require_text_keys: bool,
+
+    /// Comma-separated transforms applied in order to the decoded value before output:
+    /// `strip-tags` (replace every tagged value with its content),
+    /// `omit-null` (remove map entries whose value is null),
+    /// `normalize-text:<nfc|nfd|nfkc|nfkd>` (Unicode-normalize all text strings, including map keys),
+    /// `untag:<N>` (replace every value tagged N with its content)
+    #[arg(long = "do", value_name = "OPS", value_delimiter = ',')]
+    transforms: Vec<Transform>,
+
+    /// On a decode failure, show the input up to the failing byte as annotated hex, and what was wrong there
+    #[arg(long, default_value_t = false)]
+    explain_error: bool,
+
+    /// Report on STDERR every non-canonical encoding, misordered or duplicate map key, and other dCBOR violation in the input with its path, rather than failing at the first, and fail if there are any
+    #[arg(long, default_value_t = false)]
+    deep_strict: bool,
+
+    /// Zero-pad binary output to exactly N bytes, failing if the encoding is longer. The padded output is no longer a single well-formed CBOR item, so readers must know the real length
+    #[arg(long, value_name = "N")]
+    pad_to: Option<usize>,
+
+    /// Decode the input as a CBOR sequence (RFC 8742) of concatenated items, outputting each item in turn
+    #[arg(long, default_value_t = false)]
+    sequence: bool,
+
+    /// Output the items of a --sequence as a single array
+    #[arg(long, default_value_t = false, requires = "sequence")]
+    sequence_array: bool,
+
+    /// Output multiline diagnostic notation without annotations. Requires --out diag
+    #[arg(long, default_value_t = false, conflicts_with = "compact")]
+    pretty: bool,
+
+    /// The number of spaces per nesting level in --pretty output
+    #[arg(long, value_name = "N", default_value_t = 4, requires = "pretty")]
+    indent: usize,
+
+    /// Require hex input to be plain hex digits, rather than ignoring whitespace, `,` and `:` separators, and `0x` prefixes
+    #[arg(long, default_value_t = false)]
+    no_hex_cleanup: bool,
+
+    /// Fail if arrays, maps, and tags in the input are nested more than N deep
+    #[arg(long, value_name = "N", default_value_t = 1024)]
+    max_depth: usize,
+
+    /// Accept indefinite-length strings, arrays, and maps in the input, converting them to canonical definite-length form
+    #[arg(long, default_value_t = false, conflicts_with = "sequence")]
+    accept_indefinite: bool,
+
+    /// Treat each non-empty line of the input as a separate item in the input format, outputting each item in turn
+    #[arg(long, default_value_t = false, conflicts_with_all = ["sequence", "from_pem"])]
+    lines: bool,
+
+    /// The number of --sequence items or --lines lines to decode and format concurrently. Output is still written in the order of the input
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    jobs: u64,
+
+    /// Report on STDERR every float in the input that is not in canonical dCBOR form (whole numbers as integers, the shortest exact width, and a single NaN), and fail if there are any
+    #[arg(long, default_value_t = false)]
+    check_floats: bool,
+
+    /// Treat the hex input as chunks separated by commas or newlines, each holding one item, and output them as a sequence of items in turn or, with --concat=array, as a single array
+    #[arg(long, value_enum, value_name = "AS", num_args = 0..=1, require_equals = true, default_missing_value = "sequence", conflicts_with_all = ["sequence", "lines", "from_pem"])]
+    concat: Option<ConcatMode>,
+
+    /// Sort the elements of every array, at any depth, by their canonical encoding, after any --do transforms. This discards the order of the elements, so use it only for arrays that represent sets, e.g. before comparing two inputs
+    #[arg(long, default_value_t = false)]
+    sort_arrays: bool,
+
+    /// Encode every float at this width in bits, failing if one cannot be represented exactly. Anything but `auto` can produce non-canonical encodings, and requires bin, hex (unannotated), base64, or base64url output, or --to-pem
+    #[arg(long, value_enum, value_name = "WIDTH", default_value_t = FloatWidth::Auto)]
+    float_width: FloatWidth,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum ConcatMode {
+    /// Output each item in turn; binary output is a CBOR sequence
+    Sequence,
+    /// Output the items as a single array
+    Array,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum NormalizationForm {
+    Nfc,
+    Nfd,
+    Nfkc,
+    Nfkd,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+enum Transform {
+    StripTags,
+    OmitNull,
+    NormalizeText(NormalizationForm),
+    Untag(u64),
+}
+
+impl FromStr for Transform {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (name, param) = match s.split_once(':') {
+            Some((name, param)) => (name, Some(param)),
+            None => (s, None),
+        };
+        match (name, param) {
+            ("strip-tags", None) => Ok(Transform::StripTags),
+            ("omit-null", None) => Ok(Transform::OmitNull),
+            ("normalize-text", Some(form)) => match form {
+                "nfc" => Ok(Transform::NormalizeText(NormalizationForm::Nfc)),
+                "nfd" => Ok(Transform::NormalizeText(NormalizationForm::Nfd)),
+                "nfkc" => Ok(Transform::NormalizeText(NormalizationForm::Nfkc)),
+                "nfkd" => Ok(Transform::NormalizeText(NormalizationForm::Nfkd)),
+                _ => Err(format!("unknown normalization form: {}", form)),
+            },
+            ("untag", Some(tag)) => tag.parse().map(Transform::Untag).map_err(|_| format!("invalid tag number: {}", tag)),
+            _ => Err(format!("unknown transform: {}", s)),
+        }
+    }
+}
+
+fn warn_numeric_string_keys(cbor: &CBOR, errors: &mut dyn Write) -> Result<()> {
+    walk::visit(cbor, "$", &mut |value, path| {
+        if let CBORCase::Map(map) = value.as_case() {
+            let keys: Vec<&CBOR> = map.iter().map(|(key, _)| key).collect();
+            let all_numeric = keys.iter().all(|key| matches!(key.as_case(), CBORCase::Text(text) if text.parse::<i64>().is_ok()));
+            if keys.len() > 1 && all_numeric {
+                let sorted = keys.iter().map(|key| key.to_string()).collect::<Vec<String>>().join(", ");
+                writeln!(errors, "Warning: map at {} has only numeric text keys, which sort lexically ({}); consider integer keys", path, sorted)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+fn require_text_keys(cbor: &CBOR) -> Result<()> {
+    walk::visit(cbor, "$", &mut |value, path| {
+        if let CBORCase::Map(map) = value.as_case() {
+            if let Some((key, _)) = map.iter().find(|(key, _)| !matches!(key.as_case(), CBORCase::Text(_))) {
+                bail!("Map at {} has a non-text key: {}", path, key);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Sorts the elements of every array in `cbor` by their encoding. Nested
+/// arrays are sorted before the arrays containing them.
+fn sort_arrays(cbor: &CBOR) -> CBOR {
+    walk::rewrite(cbor, &|value| match value.as_case() {
+        CBORCase::Array(array) => {
+            let mut elements = array.clone();
+            elements.sort_by_cached_key(|element| element.to_cbor_data());
+            CBOR::from(elements)
+        },
+        _ => value,
+    })
+}
+
+fn apply_transform(cbor: &CBOR, transform: &Transform) -> CBOR {
+    match transform {
+        Transform::StripTags => walk::rewrite(cbor, &|value| match value.as_case() {
+            CBORCase::Tagged(_, item) => item.clone(),
+            _ => value,
+        }),
+        Transform::OmitNull => walk::rewrite(cbor, &|value| match value.as_case() {
+            CBORCase::Map(map) => {
+                let mut result = Map::new();
+                for (key, value) in map.iter() {
+                    if !matches!(value.as_case(), CBORCase::Simple(Simple::Null)) {
+                        result.insert(key.clone(), value.clone());
+                    }
+                }
+                result.into()
+            },
+            _ => value,
+        }),
+        Transform::NormalizeText(form) => walk::rewrite(cbor, &|value| match value.as_case() {
+            CBORCase::Text(text) => match form {
+                NormalizationForm::Nfc => text.nfc().collect::<String>().into(),
+                NormalizationForm::Nfd => text.nfd().collect::<String>().into(),
+                NormalizationForm::Nfkc => text.nfkc().collect::<String>().into(),
+                NormalizationForm::Nfkd => text.nfkd().collect::<String>().into(),
+            },
+            _ => value,
+        }),
+        Transform::Untag(number) => walk::rewrite(cbor, &|value| match value.as_case() {
+            CBORCase::Tagged(tag, item) if tag.value() == *number => item.clone(),
+            _ => value,
+        }),
+    }
+}
+
+/// Re-indents multiline diagnostic notation, which nests by four spaces per
+/// level, to `width` spaces per level. Text strings are escaped in diagnostic
+/// notation, so leading whitespace on a line is always indentation.
+fn reindent(diag: &str, width: usize) -> String {
+    diag.lines()
+        .map(|line| {
+            let content = line.trim_start_matches(' ');
+            let level = (line.len() - content.len()) / 4;
+            format!("{}{}", " ".repeat(level * width), content)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn parse_index(text: &str) -> Result<i64> {
+    text.trim().parse().map_err(|_| anyhow!("Invalid index: {}", text))
+}
+
+/// Resolves a selection like `0,2,4-6` or `-1` against an array of `len`
+/// elements, returning the selected positions in order.
+fn select_indices(spec: &str, len: usize, lenient: bool) -> Result<Vec<usize>> {
+    let mut result = vec!();
+    for part in spec.split(',') {
+        // A leading '-' is a sign, so look for the range separator after it.
+        let (start, end) = match part.char_indices().skip(1).find(|&(_, c)| c == '-') {
+            Some((position, _)) => (parse_index(&part[..position])?, parse_index(&part[position + 1..])?),
+            None => {
+                let index = parse_index(part)?;
+                (index, index)
+            },
+        };
+        let resolve = |index: i64| if index < 0 { len as i64 + index } else { index };
+        let (first, last) = (resolve(start), resolve(end));
+        if first > last {
+            bail!("Range {} ends before it starts", part.trim());
+        }
+        if !lenient {
+            for index in [start, end] {
+                if !(0..len as i64).contains(&resolve(index)) {
+                    bail!("Index {} is out of range for an array of {} elements", index, len);
+                }
+            }
+        }
+        // Clamping keeps a lenient range from counting through indices that
+        // cannot exist.
+        result.extend(first.max(0) as usize..(last.min(len as i64 - 1) + 1).max(0) as usize);
+    }
+    Ok(result)
+}
+
+impl CommandArgs {
+    /// Reports every dCBOR violation in `data` to `errors`, failing if there
+    /// are any.
+    fn check_deep_strict(&self, data: &[u8], errors: &mut dyn Write) -> Result<()> {
+        let violations = scan::scan_all(data);
+        for violation in &violations {
+            writeln!(errors, "{}", violation)?;
+        }
+        if !violations.is_empty() {
+            bail!("{} dCBOR conformance violation(s) found", violations.len());
+        }
+        Ok(())
+    }
+
+    /// Reports every non-canonical float in `data` to `errors`, failing if
+    /// there are any.
+    fn check_floats(&self, data: &[u8], errors: &mut dyn Write) -> Result<()> {
+        let violations = scan::float_violations(data);
+        for violation in &violations {
+            writeln!(errors, "{}", violation)?;
+        }
+        if !violations.is_empty() {
+            bail!("{} non-canonical float(s) found", violations.len());
+        }
+        Ok(())
+    }
+
+    /// Applies --check-floats and --deep-strict to an encoded input item.
+    fn check_encoding(&self, data: &[u8], errors: &mut dyn Write) -> Result<()> {
+        if self.check_floats {
+            self.check_floats(data, errors)?;
+        }
+        if self.deep_strict {
+            self.check_deep_strict(data, errors)?;
+        }
+        Ok(())
+    }
+
+    /// Fails if the encoded item is nested more than --max-depth deep. This
+    /// runs on the raw bytes, before the recursive decoder sees them.
+    fn check_depth(&self, data: &[u8]) -> Result<()> {
+        let depth = scan::depth(data);
+        if depth > self.max_depth {
+            bail!("Input is nested {} deep, which exceeds the maximum depth of {}", depth, self.max_depth);
+        }
+        Ok(())
+    }
+
+    /// Applies the requested checks and transforms to a decoded input item.
+    fn prepare(&self, cbor: CBOR, errors: &mut dyn Write) -> Result<CBOR> {
+        if self.tee {
+            writeln!(errors, "{}", cbor)?;
+        }
+
+        if self.require_text_keys {
+            require_text_keys(&cbor)?;
+        }
+
+        if self.warn_numeric_string_keys {
+            warn_numeric_string_keys(&cbor, errors)?;
+        }
+
+        let cbor = match &self.select {
+            Some(spec) => {
+                let CBORCase::Array(array) = cbor.as_case() else {
+                    bail!("--select requires an array as input");
+                };
+                let selected: Vec<CBOR> = select_indices(spec, array.len(), self.lenient)?
+                    .into_iter()
+                    .map(|index| array[index].clone())
+                    .collect();
+                CBOR::from(selected)
+            },
+            None => cbor,
+        };
+
+        let cbor = self.transforms.iter().fold(cbor, |cbor, transform| apply_transform(&cbor, transform));
+
+        if self.sort_arrays {
+            return Ok(sort_arrays(&cbor));
+        }
+        Ok(cbor)
+    }
+
+    fn output(&self, cbor: &CBOR, writer: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        if let Some(label) = &self.to_pem {
+            writer.write_all(format!("{}\n", pem::encode(label, &floats::encode(cbor, self.float_width)?)).as_bytes())?;
+            return Ok(());
+        }
+
+        let mut output = if self.float_width != FloatWidth::Auto {
+            let data = floats::encode(cbor, self.float_width)?;
+            match self.io.output.out {
+                OutputFormat::Bin => data,
+                OutputFormat::Hex => format!("{}\n", hex::encode(data)).into_bytes(),
+                OutputFormat::Base64 => format!("{}\n", BASE64.encode(data)).into_bytes(),
+                OutputFormat::Base64url => format!("{}\n", BASE64_URL.encode(data)).into_bytes(),
+                _ => bail!("--float-width requires bin, hex, base64, or base64url output"),
+            }
+        } else if self.pretty {
+            if self.io.output.out != OutputFormat::Diag {
+                bail!("--pretty requires diagnostic output");
+            }
+            format!("{}\n", reindent(&cbor.diagnostic_opt(false, false, false, Some(&options.tags)), self.indent)).into_bytes()
+        } else {
+            format_output(cbor, self.io.output.out, self.io.output.compact, options)?
+        };
+        if let Some(size) = self.pad_to {
+            if self.io.output.out != OutputFormat::Bin {
+                bail!("--pad-to requires binary output");
+            }
+            if output.len() > size {
+                bail!("Encoded dCBOR is {} bytes, which exceeds the padded size of {} bytes", output.len(), size);
+            }
+            output.resize(size, 0);
+        }
+        writer.write_all(&output)?;
+        Ok(())
+    }
+
+    /// Reads the encoded input for the single-item and buffered sequence
+    /// modes, from --input-file, the command line, or `reader`.
+    fn read_data(&self, reader: &mut dyn Read, json_input: &JsonInput) -> Result<Vec<u8>> {
+        if self.from_pem {
+            let text = match (&self.input_file, &self.io.input.hex) {
+                (Some(path), _) => read_text_file(path)?,
+                (None, Some(pem)) => pem.clone(),
+                (None, None) => read_text(reader)?,
+            };
+            pem::decode(&text)
+        } else if self.io.input.r#in == InputFormat::Hex && self.no_hex_cleanup {
+            let text = match (&self.input_file, &self.io.input.hex) {
+                (Some(path), _) => read_text_file(path)?,
+                (None, Some(hex)) => hex.clone(),
+                (None, None) => read_text(reader)?,
+            };
+            Ok(hex::decode(text.trim())?)
+        } else if let Some(path) = &self.input_file {
+            read_input_file(self.io.input.r#in, path, json_input)
+        } else {
+            read_input_data(self.io.input.r#in, self.io.input.hex.as_deref(), reader, json_input)
+        }
+    }
+
+    /// Runs `work` on each of `items`, passing it where to write its output
+    /// and messages, and then passes each result to `collect`. With --jobs,
+    /// items are worked on concurrently in batches of --jobs, so that a failure
+    /// stops the run shortly after the item that caused it, and each item's
+    /// output and messages are buffered and written in the order of `items`.
+    fn in_order<T, R, W, C>(&self, items: impl Iterator<Item = Result<T>>, writer: &mut dyn Write, errors: &mut dyn Write, work: W, mut collect: C) -> Result<()>
+    where
+        T: Send,
+        R: Send,
+        W: Fn(T, &mut dyn Write, &mut dyn Write) -> Result<R> + Sync,
+        C: FnMut(R),
+    {
+        if self.jobs == 1 {
+            for item in items {
+                collect(work(item?, writer, errors)?);
+            }
+            return Ok(());
+        }
+        let mut items = items.peekable();
+        while items.peek().is_some() {
+            // A batch ends at an item that could not be read, which fails the
+            // run once the items before it are written.
+            let mut batch = vec!();
+            let mut failure = None;
+            for item in items.by_ref().take(self.jobs as usize) {
+                match item {
+                    Ok(item) => batch.push(item),
+                    Err(error) => {
+                        failure = Some(error);
+                        break;
+                    },
+                }
+            }
+            let work = &work;
+            let results: Vec<(Result<R>, Vec<u8>, Vec<u8>)> = std::thread::scope(|scope| {
+                let handles: Vec<_> = batch.into_iter()
+                    .map(|item| scope.spawn(move || {
+                        let (mut output, mut messages) = (vec!(), vec!());
+                        let result = work(item, &mut output, &mut messages);
+                        (result, output, messages)
+                    }))
+                    .collect();
+                handles.into_iter()
+                    .map(|handle| handle.join().unwrap_or_else(|_| (Err(anyhow!("decoding panicked")), vec!(), vec!())))
+                    .collect()
+            });
+            for (result, output, messages) in results {
+                errors.write_all(&messages)?;
+                writer.write_all(&output)?;
+                collect(result?);
+            }
+            if let Some(error) = failure {
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    fn exec_sequence(&self, reader: impl Read, writer: &mut dyn Write, errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        let mut values = vec!();
+        let items = scan::SequenceReader::new(reader, self.max_depth);
+        let decode = |(offset, item): (usize, Vec<u8>), writer: &mut dyn Write, errors: &mut dyn Write| -> Result<Option<CBOR>> {
+            self.check_encoding(&item, errors)?;
+            let cbor = options.timings.time("decode", || decode_data(item, self.explain_error))
+                .map_err(|error| anyhow!("Sequence item at byte offset {}: {}", offset, error))?;
+            let cbor = self.prepare(cbor, errors)?;
+            if self.sequence_array {
+                return Ok(Some(cbor));
+            }
+            self.output(&cbor, writer, options)?;
+            Ok(None)
+        };
+        self.in_order(items, writer, errors, decode, |value| values.extend(value))?;
+        if self.sequence_array {
+            self.output(&CBOR::from(values), writer, options)?;
+        }
+        Ok(())
+    }
+
+    fn exec_concat(&self, text: &str, mode: ConcatMode, writer: &mut dyn Write, errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        if self.io.input.r#in != InputFormat::Hex {
+            bail!("--concat requires hex input");
+        }
+        let chunks = text.split([',', '\n']).map(str::trim).filter(|chunk| !chunk.is_empty());
+        let mut values = vec!();
+        for (index, chunk) in chunks.enumerate() {
+            let read_chunk = || -> Result<Vec<u8>> {
+                let data = if self.no_hex_cleanup { hex::decode(chunk)? } else { hex::decode(clean_hex(chunk))? };
+                self.check_depth(&data)?;
+                if self.accept_indefinite { definite::to_definite(&data) } else { Ok(data) }
+            };
+            let data = read_chunk().map_err(|error| anyhow!("Chunk {}: {}", index + 1, error))?;
+            self.check_encoding(&data, errors)?;
+            let cbor = options.timings.time("decode", || decode_data(data, self.explain_error)).map_err(|error| anyhow!("Chunk {}: {}", index + 1, error))?;
+            let cbor = self.prepare(cbor, errors)?;
+            match mode {
+                ConcatMode::Sequence => self.output(&cbor, writer, options)?,
+                ConcatMode::Array => values.push(cbor),
+            }
+        }
+        if mode == ConcatMode::Array {
+            self.output(&CBOR::from(values), writer, options)?;
+        }
+        Ok(())
+    }
+
+    fn exec_lines(&self, reader: impl BufRead, writer: &mut dyn Write, errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        if self.io.input.r#in == InputFormat::Bin {
+            bail!("--lines requires a text input format");
+        }
+        if self.io.input.r#in == InputFormat::Pem {
+            bail!("--lines cannot read PEM blocks, which span several lines");
+        }
+        let lines = reader.lines().enumerate()
+            .map(|(index, line)| Ok((index, clean_text(&line?).trim().to_string())))
+            .filter(|line| !matches!(line, Ok((_, text)) if text.is_empty()));
+        let decode = |(index, line): (usize, String), writer: &mut dyn Write, errors: &mut dyn Write| -> Result<()> {
+            let line = line.as_str();
+            let read_line = || -> Result<Vec<u8>> {
+                let data = if self.io.input.r#in == InputFormat::Hex && self.no_hex_cleanup {
+                    hex::decode(line)?
+                } else {
+                    read_input_data(self.io.input.r#in, Some(line), &mut io::empty(), &options.json_input)?
+                };
+                self.check_depth(&data)?;
+                if self.accept_indefinite { definite::to_definite(&data) } else { Ok(data) }
+            };
+            let data = read_line().map_err(|error| anyhow!("Line {}: {}", index + 1, error))?;
+            self.check_encoding(&data, errors)?;
+            let cbor = options.timings.time("decode", || decode_data(data, self.explain_error)).map_err(|error| anyhow!("Line {}: {}", index + 1, error))?;
+            let cbor = self.prepare(cbor, errors)?;
+            self.output(&cbor, writer, options)
+        };
+        self.in_order(lines, writer, errors, decode, |_| {})
+    }
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, reader: &mut dyn Read, writer: &mut dyn Write, errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        if self.lines {
+            return match (&self.input_file, &self.io.input.hex) {
+                (Some(path), _) => self.exec_lines(BufReader::new(File::open(path)?), writer, errors, options),
+                (None, Some(text)) => self.exec_lines(Cursor::new(text.as_bytes()), writer, errors, options),
+                (None, None) => self.exec_lines(BufReader::new(reader), writer, errors, options),
+            };
+        }
+
+        if let Some(mode) = self.concat {
+            let text = match (&self.input_file, &self.io.input.hex) {
+                (Some(path), _) => options.timings.time("read input", || read_text_file(path))?,
+                (None, Some(text)) => text.clone(),
+                (None, None) => options.timings.time("read input", || read_text(reader))?,
+            };
+            return self.exec_concat(&text, mode, writer, errors, options);
+        }
+
+        // Binary sequences are decoded as they are read rather than buffered.
+        if self.sequence && self.io.input.r#in == InputFormat::Bin && !self.from_pem {
+            return match &self.input_file {
+                Some(path) => self.exec_sequence(BufReader::new(File::open(path)?), writer, errors, options),
+                None => self.exec_sequence(reader, writer, errors, options),
+            };
+        }
+
+        let data = options.timings.time("read input", || self.read_data(reader, &options.json_input))?;
+
+        if self.sequence {
+            return self.exec_sequence(Cursor::new(data), writer, errors, options);
+        }
+
+        self.check_depth(&data)?;
+        let data = if self.accept_indefinite { definite::to_definite(&data)? } else { data };
+
+        self.check_encoding(&data, errors)?;
+
+        let cbor = self.prepare(options.timings.time("decode", || decode_data(data, self.explain_error))?, errors)?;
+        self.output(&cbor, writer, options)
+    }
+
+    fn writes_binary(&self) -> bool {
+        self.io.output.writes_binary() && self.to_pem.is_none()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Cursor, Read};
+    use crate::{run_on, test::{run, run_with_input, test_diag}};
+    use indoc::indoc;
+
+    #[test]
+    fn test_select() {
+        let array = "86010203040506";
+        test_diag(&["--compact", "--select", "0,2,4-6", "8701020304050607"], "[1, 3, 5, 6, 7]");
+        test_diag(&["--compact", "--select", "-1,-3--2", array], "[6, 4, 5]");
+        test_diag(&["--compact", "--select", "1,9", "--lenient", array], "[2]");
+        test_diag(&["--compact", "--select", "4--1", array], "[5, 6]");
+        test_diag(&["--compact", "--select", "0-9223372036854775807", "--lenient", array], "[1, 2, 3, 4, 5, 6]");
+        test_diag(&["--compact", "--select", "-9-1", "--lenient", array], "[1, 2]");
+        let result = run(["dcbor", "--select", "6-4", array], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Range 6-4 ends before it starts");
+        let result = run(["dcbor", "--select", "4-6", array], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Index 6 is out of range for an array of 6 elements");
+    }
+
+    #[test]
+    fn test_require_text_keys() {
+        test_diag(&["--compact", "--require-text-keys", "a1616101"], r#"{"a": 1}"#);
+        let result = run(["dcbor", "--require-text-keys", "a16161a10102"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Map at $.a has a non-text key: 1");
+    }
+
+    #[test]
+    fn test_transforms() {
+        // 40300({1: 1(2), 2: null})
+        let hex = "d99d6ca201c10202f6";
+        test_diag(&["--compact", "--do", "strip-tags", hex], "{1: 2, 2: null}");
+        test_diag(&["--compact", "--do", "untag:40300,omit-null", hex], "{1: 1(2)}");
+        // "e\u{301}" normalizes to "\u{e9}"
+        test_diag(&["--out", "hex", "--compact", "--do", "normalize-text:nfc", "6365cc81"], "62c3a9");
+    }
+
+    #[test]
+    fn test_explain_error() {
+        // [[24(1)]] encodes the integer 1 in a longer form than needed.
+        let result = run(["dcbor", "--explain-error", "81811801"], &mut Cursor::new(vec!()), &mut Vec::new());
+        let message = result.unwrap_err().to_string();
+        let explanation = message.lines().skip(1).collect::<Vec<&str>>().join("\n");
+        assert_eq!(explanation, indoc! {"
+        Decoding failed at byte offset 2: non-canonical (non-shortest) encoding of an integer or length
+        81       # array(1)
+           81    # array(1)
+              18 # ^ decoding failed here"}.trim_end());
+    }
+
+    #[test]
+    fn test_deep_strict() {
+        // {"b": [1, 0x1800], "a": 2} has a non-shortest integer and misordered keys.
+        let (mut output, mut errors) = (Vec::new(), Vec::new());
+        let result = run_on(["dcbor", "--deep-strict", "a2616282011800616102"], &|_| None, &mut Cursor::new(vec!()), &mut output, &mut errors, false);
+        assert_eq!(result.unwrap_err().to_string(), "2 dCBOR conformance violation(s) found");
+        assert!(output.is_empty());
+        assert_eq!(String::from_utf8(errors).unwrap(), indoc! {"
+        $.b[1] (byte 5): non-canonical (non-shortest) encoding of an integer or length
+        $ (byte 7): map keys are not in canonical order
+        "});
+        test_diag(&["--compact", "--deep-strict", "a2616101616202"], r#"{"a": 1, "b": 2}"#);
+    }
+
+    #[test]
+    fn test_pad_to() {
+        let mut output = Vec::new();
+        run(["dcbor", "--out", "bin", "--pad-to", "4", "1864"], &mut Cursor::new(vec!()), &mut output).unwrap();
+        assert_eq!(output, vec![0x18, 0x64, 0x00, 0x00]);
+        let result = run(["dcbor", "--out", "bin", "--pad-to", "1", "1864"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert!(result.is_err());
+        let mut output = Vec::new();
+        run(["dcbor", "--out", "bin", "--float-width", "64", "--pad-to", "12", "f93e00"], &mut Cursor::new(vec!()), &mut output).unwrap();
+        assert_eq!(output, [0xfb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let result = run(["dcbor", "--out", "hex", "--float-width", "64", "--pad-to", "12", "f93e00"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "--pad-to requires binary output");
+    }
+
+    #[test]
+    fn test_input_file() {
+        let dir = std::env::temp_dir();
+        let hex_path = dir.join(format!("dcbor-input-{}.hex", std::process::id()));
+        let bin_path = dir.join(format!("dcbor-input-{}.bin", std::process::id()));
+        std::fs::write(&hex_path, "  820102\n").unwrap();
+        std::fs::write(&bin_path, [0x82, 0x01, 0x02]).unwrap();
+        test_diag(&["--compact", "--input-file", hex_path.to_str().unwrap()], "[1, 2]");
+        test_diag(&["--compact", "--in", "bin", "--input-file", bin_path.to_str().unwrap()], "[1, 2]");
+        std::fs::remove_file(hex_path).unwrap();
+        std::fs::remove_file(bin_path).unwrap();
+    }
+
+    #[test]
+    fn test_sequence() {
+        test_diag(&["--compact", "--sequence", "01626869820203"], "1\n\"hi\"\n[2, 3]");
+        test_diag(&["--compact", "--sequence", "--sequence-array", "01626869820203"], r#"[1, "hi", [2, 3]]"#);
+        let result = run(["dcbor", "--sequence", "0162686982"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Invalid CBOR sequence at byte offset 5: expected a data item but the input ended");
+
+        // With --jobs, items are still output in order, up to the first failure.
+        let items: String = (0..20u8).map(|index| format!("8201{:02x}", index)).collect();
+        let expected: Vec<String> = (0..20).map(|index| format!("[1, {}]", index)).collect();
+        test_diag(&["--compact", "--sequence", "--jobs", "4", &items], &expected.join("\n"));
+        test_diag(&["--compact", "--sequence", "--sequence-array", "--jobs", "3", "0102030405"], "[1, 2, 3, 4, 5]");
+        let mut output = Vec::new();
+        let result = run(["dcbor", "--compact", "--sequence", "--jobs", "4", "01021801030405"], &mut Cursor::new(vec!()), &mut output);
+        assert!(result.unwrap_err().to_string().starts_with("Sequence item at byte offset 2: "));
+        assert_eq!(String::from_utf8(output).unwrap(), "1\n2\n");
+    }
+
+    #[test]
+    fn test_pretty() {
+        test_diag(&["--pretty", "--indent", "2", "a1616182c10102"], indoc! {r#"
+        {
+          "a":
+          [
+            1(1),
+            2
+          ]
+        }
+        "#}.trim());
+        let result = run(["dcbor", "--pretty", "--out", "hex", "01"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "--pretty requires diagnostic output");
+    }
+
+    #[test]
+    fn test_hex_cleanup() {
+        test_diag(&["--compact", "0x820102"], "[1, 2]");
+        test_diag(&["--compact", "82:01:02"], "[1, 2]");
+        test_diag(&["--compact", "0x82, 0x01, 0x02"], "[1, 2]");
+        assert_eq!(run_with_input(&["--compact"], b"82 01\n02\n"), "[1, 2]\n");
+        let result = run(["dcbor", "--no-hex-cleanup", "82 01 02"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert!(result.is_err());
+        // Every command reading hex accepts the same decoration.
+        test_diag(&["keys", "a1 01 02"], "1");
+        assert_eq!(run_with_input(&["values"], b"0xa1, 0x01,\n0x02\n"), "2\n");
+        test_diag(&["eq", "82 01 02", "0x820102"], "equal");
+    }
+
+    #[test]
+    fn test_max_depth() {
+        test_diag(&["--compact", "--max-depth", "3", "8181c101"], "[[1(1)]]");
+        let result = run(["dcbor", "--max-depth", "2", "8181c101"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Input is nested 3 deep, which exceeds the maximum depth of 2");
+        // The depth is checked before decoding, so deep input cannot overflow the stack.
+        let deep = format!("{}01", "81".repeat(100_000));
+        let result = run(["dcbor", deep.as_str()], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Input is nested 100000 deep, which exceeds the maximum depth of 1024");
+        let result = run(["dcbor", "--sequence", "--in", "bin"], &mut Cursor::new(hex::decode(format!("01{}", deep)).unwrap()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Sequence item at byte offset 1 is nested more than the maximum depth of 1024");
+        assert_eq!(run_with_input(&["--compact", "--accept-indefinite", "--max-depth", "2"], b"9f9f01ffff"), "[[1]]\n");
+        let result = run(["dcbor", "--accept-indefinite", "--max-depth", "1", "9f9f01ffff"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Input is nested 2 deep, which exceeds the maximum depth of 1");
+    }
+
+    #[test]
+    fn test_tee() {
+        let (mut output, mut errors) = (Vec::new(), Vec::new());
+        run_on(["dcbor", "--tee", "--out", "hex", "--compact", "a1616182f5f6"], &|_| None, &mut Cursor::new(vec!()), &mut output, &mut errors, false).unwrap();
+        assert_eq!(output, b"a1616182f5f6\n");
+        assert_eq!(errors, b"{\"a\": [true, null]}\n");
+        // The input is echoed even when a check then rejects it.
+        let mut errors = Vec::new();
+        let result = run_on(["dcbor", "--tee", "--require-text-keys", "a10102"], &|_| None, &mut Cursor::new(vec!()), &mut Vec::new(), &mut errors, false);
+        assert!(result.is_err());
+        assert_eq!(errors, b"{1: 2}\n");
+    }
+
+    #[test]
+    fn test_warnings() {
+        // {"a": {"1": true, "10": false}}
+        let mut errors = Vec::new();
+        run_on(["dcbor", "--warn-numeric-string-keys", "--out", "none", "a16161a26131f5623130f4"], &|_| None, &mut Cursor::new(vec!()), &mut Vec::new(), &mut errors, false).unwrap();
+        assert_eq!(String::from_utf8(errors).unwrap(), "Warning: map at $.a has only numeric text keys, which sort lexically (\"1\", \"10\"); consider integer keys\n");
+
+        let path = std::env::temp_dir().join(format!("dcbor-warn-tags-{}.csv", std::process::id()));
+        std::fs::write(&path, "1,timestamp\n").unwrap();
+        let mut errors = Vec::new();
+        run_on(["dcbor", "--tags", path.to_str().unwrap(), "c101"], &|_| None, &mut Cursor::new(vec!()), &mut Vec::new(), &mut errors, false).unwrap();
+        assert_eq!(String::from_utf8(errors).unwrap(), "Warning: tag 1 is already registered as \"date\"; ignoring \"timestamp\"\n");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_sequence_streaming() {
+        // A sequence of several megabytes, larger than any one read chunk, with
+        // items straddling the chunk boundaries.
+        let item = hex::decode(format!("8201781e{}", "61".repeat(30))).unwrap();
+        let input = item.repeat(100_000);
+        let mut output = Vec::new();
+        run(["dcbor", "--in", "bin", "--out", "bin", "--sequence"], &mut Cursor::new(input.clone()), &mut output).unwrap();
+        assert_eq!(output, input);
+
+        let mut truncated = input.clone();
+        truncated.pop();
+        let result = run(["dcbor", "--in", "bin", "--out", "none", "--sequence"], &mut Cursor::new(truncated), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), format!("Invalid CBOR sequence at byte offset {}: string extends past the end of the input", input.len() - item.len() + 2));
+
+        // Items are output as soon as they arrive, without waiting for the
+        // reader to fill a whole chunk.
+        struct Trickle(Vec<&'static [u8]>);
+        impl Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(io::Error::other("no more input yet"));
+                }
+                let piece = self.0.remove(0);
+                buf[..piece.len()].copy_from_slice(piece);
+                Ok(piece.len())
+            }
+        }
+        let mut output = Vec::new();
+        let result = run(["dcbor", "--in", "bin", "--out", "hex", "--sequence"], &mut Trickle(vec!(b"\x01\x82", b"\x02\x03")), &mut output);
+        assert_eq!(result.unwrap_err().to_string(), "no more input yet");
+        assert_eq!(output, b"01\n820203\n");
+    }
+
+    #[test]
+    fn test_accept_indefinite() {
+        // [_ 1, (_ "a", "b"), {_ "b": 1, "a": 2}]
+        test_diag(&["--compact", "--accept-indefinite", "9f017f61616162ffbf616201616102ffff"], r#"[1, "ab", {"a": 2, "b": 1}]"#);
+        assert_eq!(run_with_input(&["--out", "hex", "--compact", "--accept-indefinite", "5f4101ff"], &[]), "4101\n");
+        let result = run(["dcbor", "9f01ff"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert!(result.is_err());
+        let result = run(["dcbor", "--accept-indefinite", "bbffffffffffffffff"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Map at byte offset 0 has more entries than can be encoded");
+    }
+
+    #[test]
+    fn test_lines() {
+        assert_eq!(run_with_input(&["--compact", "--lines"], b"01\n\n820102\r\n  6161\n"), "1\n[1, 2]\n\"a\"\n");
+        assert_eq!(run_with_input(&["--compact", "--lines", "--in", "json", "--out", "hex"], b"[1]\n{\"a\": 1}\n"), "8101\na1616101\n");
+        let result = run(["dcbor", "--lines"], &mut Cursor::new(b"01\n8201\n".to_vec()), &mut Vec::new());
+        assert!(result.unwrap_err().to_string().starts_with("Line 2: "));
+        let input: String = (0..20).map(|index| format!("{:02x}\n\n", index)).collect();
+        let expected: String = (0..20).map(|index| format!("{}\n", index)).collect();
+        assert_eq!(run_with_input(&["--lines", "--jobs", "8"], input.as_bytes()), expected);
+        let mut output = Vec::new();
+        let result = run(["dcbor", "--lines", "--jobs", "8"], &mut Cursor::new(b"01\n02\n8201\n03\n".to_vec()), &mut output);
+        assert!(result.unwrap_err().to_string().starts_with("Line 3: "));
+        assert_eq!(String::from_utf8(output).unwrap(), "1\n2\n");
+    }
+
+    #[test]
+    fn test_check_floats() {
+        // [1.0 (half), 1.5 (single), Infinity (double), NaN (double), NaN with a payload (half), 1.1 (double)]
+        let (mut output, mut errors) = (Vec::new(), Vec::new());
+        let args = ["dcbor", "--check-floats", "86f93c00fa3fc00000fb7ff0000000000000fb7ff8000000000000f97e01fb3ff199999999999a"];
+        let result = run_on(args, &|_| None, &mut Cursor::new(vec!()), &mut output, &mut errors, false);
+        assert_eq!(result.unwrap_err().to_string(), "5 non-canonical float(s) found");
+        assert!(output.is_empty());
+        assert_eq!(String::from_utf8(errors).unwrap(), indoc! {"
+        $[0] (byte 1): the whole-number float 1.0 must be encoded as an integer
+        $[1] (byte 4): the float 1.5 must be encoded in 3 bytes, not 5
+        $[2] (byte 9): the float inf must be encoded in 3 bytes, not 9
+        $[3] (byte 18): NaN must be encoded as the half-precision value f97e00
+        $[4] (byte 27): NaN must be encoded as the half-precision value f97e00
+        "});
+        test_diag(&["--compact", "--check-floats", "83f93e00f97e00f9fc00"], "[1.5, NaN, -Infinity]");
+
+        // Each item of a sequence, concatenation, or line is checked, with the
+        // reports kept out of the output.
+        for args in [&["--sequence"][..], &["--concat"], &["--lines"]] {
+            let (mut output, mut errors) = (Vec::new(), Vec::new());
+            let args = ["dcbor", "--check-floats"].iter().chain(args).copied();
+            let result = run_on(args, &|_| None, &mut Cursor::new(b"01\nfa3fc00000\n".to_vec()), &mut output, &mut errors, false);
+            assert_eq!(result.unwrap_err().to_string(), "1 non-canonical float(s) found");
+            assert_eq!(String::from_utf8(output).unwrap(), "1\n");
+            assert_eq!(String::from_utf8(errors).unwrap(), "$ (byte 0): the float 1.5 must be encoded in 3 bytes, not 5\n");
+        }
+    }
+
+    #[test]
+    fn test_concat() {
+        test_diag(&["--concat", "--out", "hex", "--compact", "01, 6161\n82f5f6"], "01\n6161\n82f5f6");
+        test_diag(&["--concat=array", "--compact", "01,6161,82f5f6"], r#"[1, "a", [true, null]]"#);
+        let output = run_with_input(&["--concat", "--out", "bin"], b"01\n0x6161\n");
+        assert_eq!(output.as_bytes(), [0x01, 0x61, 0x61]);
+        let result = run(["dcbor", "--concat", "01,82f5,02"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert!(result.unwrap_err().to_string().starts_with("Chunk 2: "));
+    }
+
+    #[test]
+    fn test_sort_arrays() {
+        // [3, [2, "b", 1], {"k": [10, -1]}, "a", 1]
+        let hex = run_with_input(&["--in", "json", "--out", "hex", "--compact", r#"[3, [2, "b", 1], {"k": [10, -1]}, "a", 1]"#], &[]);
+        test_diag(&["--sort-arrays", "--compact", hex.trim()], r#"[1, 3, "a", [1, 2, "b"], {"k": [10, -1]}]"#);
+        test_diag(&["--compact", hex.trim()], r#"[3, [2, "b", 1], {"k": [10, -1]}, "a", 1]"#);
+    }
+
+    #[test]
+    fn test_float_width() {
+        // [1.5, {"a": -Infinity}, NaN]
+        let hex = "83f93e00a16161f9fc00f97e00";
+        test_diag(&["--out", "hex", "--compact", "--float-width", "auto", hex], "83f93e00a16161f9fc00f97e00");
+        test_diag(&["--out", "hex", "--compact", "--float-width", "32", hex], "83fa3fc00000a16161faff800000fa7fc00000");
+        test_diag(&["--out", "hex", "--compact", "--float-width", "64", hex], "83fb3ff8000000000000a16161fbfff0000000000000fb7ff8000000000000");
+        test_diag(&["--out", "hex", "--compact", "--float-width", "16", "82f93e00f90001"], "82f93e00f90001");
+        // 1.1 is only exact in double precision.
+        let result = run(["dcbor", "--out", "hex", "--float-width", "32", "a16178fb3ff199999999999a"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "The float 1.1 at $.x cannot be encoded in 32 bits without loss");
+        let result = run(["dcbor", "--float-width", "64", hex], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "--float-width requires bin, hex, base64, or base64url output");
+    }
+}
@@ -0,0 +1,228 @@
+use std::io::{Read, Write};
+
+use anyhow::{Result, bail};
+use clap::{Args, ValueEnum};
+use dcbor::prelude::*;
+
+use crate::{cmd::{Exec, PairArgs}, walk, FormatOptions};
+
+/// Compare two dCBOR values structurally
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    #[command(flatten)]
+    pair: PairArgs,
+
+    /// How to report the differences
+    #[arg(long, value_enum, default_value_t = DiffFormat::Unified)]
+    format: DiffFormat,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+#[doc(hidden)]
+enum DiffFormat {
+    /// An indented tree of the maps and arrays that differ, with a `-`, `+`, or `~` line for each difference
+    Unified,
+    /// A JSON array of {"op", "path", "old", "new"} objects, with the values in diagnostic notation (null when absent)
+    Json,
+    /// One line for each difference, with the path and the left and right values in columns
+    SideBySide,
+}
+
+/// A single difference between the two values.
+struct Change {
+    /// The labels of the containers holding the difference, from the root, and
+    /// then of the difference itself, as shown in the unified format.
+    labels: Vec<String>,
+    /// The path of the difference from the root (`$`).
+    path: String,
+    /// The left value, or `None` if it was added.
+    old: Option<CBOR>,
+    /// The right value, or `None` if it was removed.
+    new: Option<CBOR>,
+}
+
+impl Change {
+    fn op(&self) -> &'static str {
+        match (&self.old, &self.new) {
+            (Some(_), Some(_)) => "change",
+            (Some(_), None) => "remove",
+            _ => "add",
+        }
+    }
+}
+
+/// Appends each difference between `left` and `right`, which are at `path`
+/// and labeled `labels`, to `changes`. Maps and arrays (and tagged values with
+/// the same tag) are compared element by element; anything else is reported
+/// as a change.
+fn diff(labels: &[String], path: &str, left: Option<&CBOR>, right: Option<&CBOR>, changes: &mut Vec<Change>) {
+    let (Some(left), Some(right)) = (left, right) else {
+        changes.push(Change { labels: labels.to_vec(), path: path.to_string(), old: left.cloned(), new: right.cloned() });
+        return;
+    };
+    if left == right {
+        return;
+    }
+    let child = |label: String| [labels, &[label]].concat();
+    match (left.as_case(), right.as_case()) {
+        (CBORCase::Map(left_map), CBORCase::Map(right_map)) => {
+            let mut keys: Vec<&CBOR> = left_map.iter().chain(right_map.iter()).map(|(key, _)| key).collect();
+            keys.sort_by_key(|key| key.to_cbor_data());
+            keys.dedup();
+            for key in keys {
+                let left_value = left_map.iter().find(|(k, _)| *k == key).map(|(_, v)| v);
+                let right_value = right_map.iter().find(|(k, _)| *k == key).map(|(_, v)| v);
+                diff(&child(format!("{}:", key)), &walk::key_path(path, key), left_value, right_value, changes);
+            }
+        },
+        (CBORCase::Array(left_array), CBORCase::Array(right_array)) => {
+            for index in 0..left_array.len().max(right_array.len()) {
+                diff(&child(format!("[{}]:", index)), &format!("{}[{}]", path, index), left_array.get(index), right_array.get(index), changes);
+            }
+        },
+        (CBORCase::Tagged(left_tag, left_item), CBORCase::Tagged(right_tag, right_item)) if left_tag.value() == right_tag.value() => {
+            diff(&child(format!("tag({}):", left_tag.value())), path, Some(left_item), Some(right_item), changes);
+        },
+        _ => changes.push(Change { labels: labels.to_vec(), path: path.to_string(), old: Some(left.clone()), new: Some(right.clone()) }),
+    }
+}
+
+/// Renders `changes` as an indented tree, with a line for each container
+/// holding a difference before the differences within it.
+fn unified(changes: &[Change]) -> Vec<String> {
+    let mut lines = vec!();
+    let mut containers: &[String] = &[];
+    for change in changes {
+        let (label, parents) = change.labels.split_last().expect("every change has a label");
+        let shared = containers.iter().zip(parents).take_while(|(a, b)| a == b).count();
+        for (depth, parent) in parents.iter().enumerate().skip(shared) {
+            lines.push(format!("{}{}", "  ".repeat(depth), parent));
+        }
+        containers = parents;
+        let indent = "  ".repeat(parents.len());
+        match (&change.old, &change.new) {
+            (Some(old), Some(new)) => lines.push(format!("{}~ {} {} -> {}", indent, label, old, new)),
+            (Some(old), None) => lines.push(format!("{}- {} {}", indent, label, old)),
+            (None, Some(new)) => lines.push(format!("{}+ {} {}", indent, label, new)),
+            (None, None) => {},
+        }
+    }
+    lines
+}
+
+/// Renders `changes` as a JSON array of change objects.
+fn json(changes: &[Change]) -> Result<Vec<String>> {
+    let changes: Vec<serde_json::Value> = changes.iter()
+        .map(|change| serde_json::json!({
+            "op": change.op(),
+            "path": change.path,
+            "old": change.old.as_ref().map(CBOR::to_string),
+            "new": change.new.as_ref().map(CBOR::to_string),
+        }))
+        .collect();
+    Ok(vec!(serde_json::to_string_pretty(&changes)?))
+}
+
+/// Renders `changes` in three columns: the path, the left value, and the right
+/// value, marked `|` if changed, `<` if removed, or `>` if added.
+fn side_by_side(changes: &[Change]) -> Vec<String> {
+    let rows: Vec<(&str, String, String, char)> = changes.iter()
+        .map(|change| {
+            let marker = match change.op() {
+                "change" => '|',
+                "remove" => '<',
+                _ => '>',
+            };
+            let value = |value: &Option<CBOR>| value.as_ref().map(CBOR::to_string).unwrap_or_default();
+            (change.path.as_str(), value(&change.old), value(&change.new), marker)
+        })
+        .collect();
+    let path_width = rows.iter().map(|(path, ..)| path.chars().count()).max().unwrap_or(0);
+    let old_width = rows.iter().map(|(_, old, ..)| old.chars().count()).max().unwrap_or(0);
+    rows.into_iter()
+        .map(|(path, old, new, marker)| {
+            format!("{:<path_width$}  {:<old_width$}  {} {}", path, old, marker, new).trim_end().to_string()
+        })
+        .collect()
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, _reader: &mut dyn Read, writer: &mut dyn Write, _errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        let (left, right) = self.pair.read("diff", options)?;
+
+        let mut changes = vec!();
+        diff(&["$".to_string()], "$", Some(&left), Some(&right), &mut changes);
+        let lines = match self.format {
+            DiffFormat::Unified => unified(&changes),
+            DiffFormat::Json => json(&changes)?,
+            DiffFormat::SideBySide => side_by_side(&changes),
+        };
+        for line in lines {
+            writeln!(writer, "{}", line)?;
+        }
+        if !changes.is_empty() {
+            bail!("Values differ");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::test::run;
+    use indoc::indoc;
+
+    #[test]
+    fn test_diff() {
+        // {"a": 1, "b": [1, 2, 3], "c": "x"} vs {"a": 1, "b": [1, 5], "d": true}
+        let left = "a361610161628301020361636178";
+        let right = "a361610161628201056164f5";
+        let mut output = Vec::new();
+        let result = run(["dcbor", "diff", left, right], &mut Cursor::new(vec!()), &mut output);
+        assert_eq!(result.unwrap_err().to_string(), "Values differ");
+        assert_eq!(String::from_utf8(output).unwrap(), indoc! {r#"
+        $
+          "b":
+            ~ [1]: 2 -> 5
+            - [2]: 3
+          - "c": "x"
+          + "d": true
+        "#});
+        run(["dcbor", "diff", left, left], &mut Cursor::new(vec!()), &mut Vec::new()).unwrap();
+        let mut output = Vec::new();
+        assert!(run(["dcbor", "diff", "82010a", "820114"], &mut Cursor::new(vec!()), &mut output).is_err());
+        assert_eq!(String::from_utf8(output).unwrap(), "$\n  ~ [1]: 10 -> 20\n");
+
+        let mut output = Vec::new();
+        assert!(run(["dcbor", "diff", "--format", "json", left, right], &mut Cursor::new(vec!()), &mut output).is_err());
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(json, serde_json::json!([
+            {"op": "change", "path": "$.b[1]", "old": "2", "new": "5"},
+            {"op": "remove", "path": "$.b[2]", "old": "3", "new": null},
+            {"op": "remove", "path": "$.c", "old": "\"x\"", "new": null},
+            {"op": "add", "path": "$.d", "old": null, "new": "true"},
+        ]));
+        let mut output = Vec::new();
+        run(["dcbor", "diff", "--format", "json", left, left], &mut Cursor::new(vec!()), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "[]\n");
+
+        let mut output = Vec::new();
+        assert!(run(["dcbor", "diff", "--format", "side-by-side", left, right], &mut Cursor::new(vec!()), &mut output).is_err());
+        assert_eq!(String::from_utf8(output).unwrap(), indoc! {r#"
+        $.b[1]  2    | 5
+        $.b[2]  3    <
+        $.c     "x"  <
+        $.d          > true
+        "#});
+
+        // Binary input comes only from files.
+        let result = run(["dcbor", "diff", "--in", "bin", left, left], &mut Cursor::new(vec!(0x01)), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Binary input cannot be given on the command line; read it from files with --left and --right");
+        let path = std::env::temp_dir().join(format!("dcbor-diff-{}.bin", std::process::id()));
+        std::fs::write(&path, hex::decode(left).unwrap()).unwrap();
+        run(["dcbor", "diff", "--in", "bin", "--left", path.to_str().unwrap(), "--right", path.to_str().unwrap()], &mut Cursor::new(vec!()), &mut Vec::new()).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
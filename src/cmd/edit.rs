@@ -0,0 +1,126 @@
+use std::io::{Read, Write};
+
+use anyhow::{Result, bail};
+use clap::Args;
+use dcbor::prelude::*;
+
+use crate::{cmd::{Exec, IoArgs, select::{parse_path, Segment}}, read_input, FormatOptions, InputFormat};
+
+/// Replace or delete the value at a path such as $.users[0].name
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    /// The path to edit, in the syntax of the select command
+    #[arg(value_name = "PATH")]
+    path: String,
+
+    #[command(flatten)]
+    io: IoArgs,
+
+    /// Replace the value at the path with this value, given in the --value-in format. A missing final map key is added
+    #[arg(long, value_name = "VALUE")]
+    replace: Option<String>,
+
+    /// Remove the map entry or array element at the path
+    #[arg(long, default_value_t = false, conflicts_with = "replace", required_unless_present = "replace")]
+    delete: bool,
+
+    /// The format of the --replace value
+    #[arg(long, value_enum, default_value_t = InputFormat::Hex)]
+    value_in: InputFormat,
+}
+
+enum Edit {
+    Replace(CBOR),
+    Delete,
+}
+
+/// Rebuilds `cbor` with `edit` applied at the end of `segments`, looking
+/// through (and keeping) tags as select does. `path` is the path to `cbor`.
+fn apply(cbor: &CBOR, segments: &[Segment], path: &str, edit: &Edit) -> Result<CBOR> {
+    let Some((segment, rest)) = segments.split_first() else {
+        return match edit {
+            Edit::Replace(value) => Ok(value.clone()),
+            Edit::Delete => bail!("Cannot delete the whole input"),
+        };
+    };
+    let child_path = format!("{}{}", path, segment.text());
+    let result = match (segment, cbor.as_case()) {
+        (_, CBORCase::Tagged(tag, item)) => CBOR::to_tagged_value(tag.clone(), apply(item, segments, path, edit)?),
+        (Segment::Key(key, text), CBORCase::Map(map)) => {
+            let mut result = Map::new();
+            let mut found = false;
+            for (candidate, value) in map.iter() {
+                if candidate != key {
+                    result.insert(candidate.clone(), value.clone());
+                    continue;
+                }
+                found = true;
+                match (rest.is_empty(), edit) {
+                    (true, Edit::Delete) => {},
+                    _ => result.insert(candidate.clone(), apply(value, rest, &child_path, edit)?),
+                }
+            }
+            match (found, rest.is_empty(), edit) {
+                (true, _, _) => {},
+                (false, true, Edit::Replace(value)) => result.insert(key.clone(), value.clone()),
+                (false, _, _) => bail!("No key {} at {}", text, path),
+            }
+            result.into()
+        },
+        (Segment::Index(index, text), CBORCase::Array(array)) => {
+            if *index >= array.len() {
+                bail!("Index {} is out of range for the array of {} element(s) at {}", text, array.len(), path);
+            }
+            let mut elements = array.clone();
+            match (rest.is_empty(), edit) {
+                (true, Edit::Delete) => {
+                    elements.remove(*index);
+                },
+                _ => elements[*index] = apply(&elements[*index], rest, &child_path, edit)?,
+            }
+            CBOR::from(elements)
+        },
+        (Segment::Key(_, text), _) => bail!("Cannot look up {} because the value at {} is not a map", text, path),
+        (Segment::Index(_, text), _) => bail!("Cannot look up {} because the value at {} is not an array", text, path),
+    };
+    Ok(result)
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, reader: &mut dyn Read, writer: &mut dyn Write, _errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        let segments = parse_path(&self.path)?;
+        let edit = match &self.replace {
+            Some(value) => Edit::Replace(read_input(self.value_in, Some(value), &mut std::io::empty(), &options.json_input, false)?),
+            None => Edit::Delete,
+        };
+        let cbor = self.io.input.read(reader, options)?;
+        let cbor = apply(&cbor, &segments, "$", &edit)?;
+        self.io.output.write(&cbor, writer, options)?;
+        Ok(())
+    }
+
+    fn writes_binary(&self) -> bool {
+        self.io.output.writes_binary()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::test::{run, run_with_input, test_diag};
+
+    #[test]
+    fn test_edit() {
+        let hex = run_with_input(&["--in", "json", "--out", "hex", "--compact", r#"{"users": [{"name": "Al"}, {"name": "Bo"}]}"#], &[]);
+        let hex = hex.trim();
+        test_diag(&["edit", "--compact", "$.users[0].name", "--replace", "\"Cy\"", "--value-in", "json", hex], r#"{"users": [{"name": "Cy"}, {"name": "Bo"}]}"#);
+        test_diag(&["edit", "--compact", "$.users[1].age", "--replace", "1820", hex], r#"{"users": [{"name": "Al"}, {"age": 32, "name": "Bo"}]}"#);
+        test_diag(&["edit", "--compact", "$.users[0]", "--delete", hex], r#"{"users": [{"name": "Bo"}]}"#);
+        test_diag(&["edit", "--compact", "$.1", "--delete", "c1a2010203f6"], "1({3: null})");
+        let result = run(["dcbor", "edit", "$.users[2]", "--delete", hex], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Index [2] is out of range for the array of 2 element(s) at $.users");
+        let result = run(["dcbor", "edit", "$.users[0].age", "--delete", hex], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "No key .age at $.users[0]");
+    }
+}
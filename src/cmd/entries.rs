@@ -0,0 +1,67 @@
+use std::io::{Read, Write};
+
+use anyhow::{Result, bail};
+use clap::{Args, ValueEnum};
+use dcbor::prelude::*;
+
+use crate::{cmd::{Exec, InputArgs}, json, FormatOptions};
+
+/// Output the entries of a map, one `key => value` per line, in canonical order
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// The output format
+    #[arg(short, long, value_enum, default_value_t = EntriesFormat::Diag)]
+    out: EntriesFormat,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+#[doc(hidden)]
+enum EntriesFormat {
+    /// One `key => value` line per entry, in compact diagnostic notation
+    Diag,
+    /// A JSON array of [key, value] pairs, converted as for JSON output
+    Json,
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, reader: &mut dyn Read, writer: &mut dyn Write, _errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        let cbor = self.input.read(reader, options)?;
+        let CBORCase::Map(map) = cbor.as_case() else {
+            bail!("entries requires a map as input");
+        };
+        match self.out {
+            EntriesFormat::Diag => {
+                for (key, value) in map.iter() {
+                    writeln!(writer, "{} => {}", key, value)?;
+                }
+            },
+            EntriesFormat::Json => {
+                let pairs = map.iter()
+                    .map(|(key, value)| Ok(serde_json::Value::Array(vec!(json::from_cbor(key)?, json::from_cbor(value)?))))
+                    .collect::<Result<Vec<_>>>()?;
+                writeln!(writer, "{}", serde_json::to_string_pretty(&pairs)?)?;
+            },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::test::{run, run_with_input, test_diag};
+
+    #[test]
+    fn test_entries() {
+        test_diag(&["entries", "a30161616161820102626b6b01"], "1 => \"a\"\n\"a\" => [1, 2]\n\"kk\" => 1");
+        let output = run_with_input(&["entries", "--out", "json", "a2016161616102"], &[]);
+        let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(json, serde_json::json!([[1, "a"], ["a", 2]]));
+        let result = run(["dcbor", "entries", "820102"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "entries requires a map as input");
+    }
+}
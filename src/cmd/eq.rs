@@ -0,0 +1,53 @@
+use std::io::{Read, Write};
+
+use anyhow::{Result, bail};
+use clap::Args;
+
+use crate::{cmd::{Exec, PairArgs}, FormatOptions};
+
+/// Check that two inputs encode the same dCBOR value, failing if they do not
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    #[command(flatten)]
+    pair: PairArgs,
+
+    /// Don't print `equal` or `not equal`; only the exit status reports the result
+    #[arg(short, long, default_value_t = false)]
+    quiet: bool,
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, _reader: &mut dyn Read, writer: &mut dyn Write, _errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        let (left, right) = self.pair.read("eq", options)?;
+
+        let equal = left.to_cbor_data() == right.to_cbor_data();
+        if !self.quiet {
+            writeln!(writer, "{}", if equal { "equal" } else { "not equal" })?;
+        }
+        if !equal {
+            bail!("Values are not equal");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::test::{run, test_diag};
+
+    #[test]
+    fn test_eq() {
+        test_diag(&["eq", "--in", "json", r#"{"b": 1, "a": [true]}"#, r#"{"a": [true], "b": 1}"#], "equal");
+        let mut output = Vec::new();
+        let result = run(["dcbor", "eq", "01", "02"], &mut Cursor::new(vec!()), &mut output);
+        assert_eq!(result.unwrap_err().to_string(), "Values are not equal");
+        assert_eq!(output, b"not equal\n");
+        let mut output = Vec::new();
+        assert!(run(["dcbor", "eq", "--quiet", "01", "02"], &mut Cursor::new(vec!()), &mut output).is_err());
+        assert!(output.is_empty());
+        let result = run(["dcbor", "eq", "--in", "bin", "01", "02"], &mut Cursor::new(vec!(0x01)), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Binary input cannot be given on the command line; read it from files with --left and --right");
+    }
+}
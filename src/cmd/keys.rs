@@ -0,0 +1,46 @@
+use std::io::{Read, Write};
+
+use anyhow::{Result, bail};
+use clap::Args;
+use dcbor::prelude::*;
+
+use crate::{cmd::{Exec, IoArgs}, FormatOptions};
+
+/// Output the keys of a map, one per line, in canonical order
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    #[command(flatten)]
+    io: IoArgs,
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, reader: &mut dyn Read, writer: &mut dyn Write, _errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        let cbor = self.io.input.read(reader, options)?;
+        let CBORCase::Map(map) = cbor.as_case() else {
+            bail!("keys requires a map as input");
+        };
+        // dCBOR maps iterate in canonical (encoded byte) order of their keys.
+        for (key, _) in map.iter() {
+            self.io.output.write(key, writer, options)?;
+        }
+        Ok(())
+    }
+
+    fn writes_binary(&self) -> bool {
+        self.io.output.writes_binary()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::test::{run, test_diag};
+
+    #[test]
+    fn test_keys() {
+        test_diag(&["keys", "--compact", "a30161616161820102626b6b01"], "1\n\"a\"\n\"kk\"");
+        let result = run(["dcbor", "keys", "820102"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "keys requires a map as input");
+    }
+}
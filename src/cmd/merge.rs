@@ -0,0 +1,178 @@
+use std::{io::{Read, Write}, path::Path};
+
+use anyhow::{Result, bail};
+use clap::{Args, ValueEnum};
+use dcbor::prelude::*;
+
+use crate::{cmd::{Exec, OutputArgs}, decode_data, read_input_data, read_input_file, walk, FormatOptions, InputFormat};
+
+/// Merge several dCBOR maps into one
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    /// The maps to merge as text in the input format (hexadecimal by default), or file paths with --files. If none are given, a single map is read from STDIN
+    #[arg(value_name = "INPUT")]
+    inputs: Vec<String>,
+
+    /// Treat the inputs as paths of files to read
+    #[arg(long, default_value_t = false)]
+    files: bool,
+
+    /// Fail if a key appears in more than one map, rather than letting the later map's value win. With --deep, only keys whose values cannot be combined conflict
+    #[arg(long, default_value_t = false)]
+    error_on_conflict: bool,
+
+    /// Merge maps under the same key recursively, rather than letting the later map replace the earlier one
+    #[arg(long, default_value_t = false)]
+    deep: bool,
+
+    /// How --deep combines arrays under the same key
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = MergeArrays::Replace, requires = "deep")]
+    merge_arrays: MergeArrays,
+
+    /// The input format
+    #[arg(short, long, value_enum, default_value_t = InputFormat::Hex)]
+    r#in: InputFormat,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+#[doc(hidden)]
+enum MergeArrays {
+    /// The later array replaces the earlier one: [1, 2] and [2, 3] give [2, 3]
+    Replace,
+    /// The later array's items follow the earlier one's: [1, 2] and [2, 3] give [1, 2, 2, 3]
+    Concat,
+    /// As with concat, but without repeating any item: [1, 2] and [2, 3] give [1, 2, 3]
+    Union,
+}
+
+impl CommandArgs {
+    fn read(&self, input: Option<&str>, reader: &mut dyn Read, options: &FormatOptions) -> Result<CBOR> {
+        let data = match input {
+            Some(input) if self.files => options.timings.time("read input", || read_input_file(self.r#in, Path::new(input), &options.json_input))?,
+            Some(_) if self.r#in == InputFormat::Bin => bail!("Binary input cannot be given on the command line; pass file paths with --files"),
+            _ => options.timings.time("read input", || read_input_data(self.r#in, input, reader, &options.json_input))?,
+        };
+        options.timings.time("decode", || decode_data(data, false))
+    }
+
+    /// Merges `map`, from input number `input`, into `merged`, the map at
+    /// `path` in the result.
+    fn merge_into(&self, merged: &mut Map, map: &Map, path: &str, input: usize) -> Result<()> {
+        for (key, value) in map.iter() {
+            let existing = merged.iter().find(|(merged_key, _)| *merged_key == key).map(|(_, value)| value.clone());
+            let value = match existing {
+                Some(existing) => match self.combine(&existing, value, &walk::key_path(path, key), input)? {
+                    Some(combined) => combined,
+                    None if self.error_on_conflict => {
+                        let location = if path == "$" { String::new() } else { format!(" at {}", path) };
+                        bail!("Key {}{} in input {} is already present in an earlier input", key, location, input);
+                    },
+                    None => value.clone(),
+                },
+                None => value.clone(),
+            };
+            merged.insert(key.clone(), value);
+        }
+        Ok(())
+    }
+
+    /// Combines two values under the same key with --deep, or returns `None`
+    /// if they cannot be combined and the later one should win.
+    fn combine(&self, earlier: &CBOR, later: &CBOR, path: &str, input: usize) -> Result<Option<CBOR>> {
+        if !self.deep {
+            return Ok(None);
+        }
+        let combined = match (earlier.as_case(), later.as_case(), self.merge_arrays) {
+            (CBORCase::Map(earlier), CBORCase::Map(later), _) => {
+                let mut merged = earlier.clone();
+                self.merge_into(&mut merged, later, path, input)?;
+                merged.into()
+            },
+            (CBORCase::Array(earlier), CBORCase::Array(later), MergeArrays::Concat) => {
+                earlier.iter().chain(later).cloned().collect::<Vec<CBOR>>().into()
+            },
+            (CBORCase::Array(earlier), CBORCase::Array(later), MergeArrays::Union) => {
+                let mut items: Vec<CBOR> = vec!();
+                for item in earlier.iter().chain(later) {
+                    if !items.contains(item) {
+                        items.push(item.clone());
+                    }
+                }
+                items.into()
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(combined))
+    }
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, reader: &mut dyn Read, writer: &mut dyn Write, _errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        let inputs = if self.inputs.is_empty() {
+            vec!(self.read(None, reader, options)?)
+        } else {
+            self.inputs.iter().map(|input| self.read(Some(input), reader, options)).collect::<Result<Vec<_>>>()?
+        };
+        let mut merged = Map::new();
+        for (index, input) in inputs.iter().enumerate() {
+            let CBORCase::Map(map) = input.as_case() else {
+                bail!("Input {} is not a map", index + 1);
+            };
+            self.merge_into(&mut merged, map, "$", index + 1)?;
+        }
+        self.output.write(&merged.into(), writer, options)?;
+        Ok(())
+    }
+
+    fn writes_binary(&self) -> bool {
+        self.output.writes_binary()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::test::{run, run_with_input, test_diag};
+
+    #[test]
+    fn test_merge() {
+        // {"a": 1, "b": 2} and {"b": 3, "c": 4}
+        test_diag(&["merge", "--compact", "a2616101616202", "a2616203616304"], r#"{"a": 1, "b": 3, "c": 4}"#);
+        test_diag(&["merge", "--compact", "a2616101616202"], r#"{"a": 1, "b": 2}"#);
+        let result = run(["dcbor", "merge", "--error-on-conflict", "a2616101616202", "a2616203616304"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), r#"Key "b" in input 2 is already present in an earlier input"#);
+        let result = run(["dcbor", "merge", "a2616101616202", "82616203"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Input 2 is not a map");
+        let result = run(["dcbor", "merge", "--in", "bin", "a", "b"], &mut Cursor::new(hex::decode("a2616101616202").unwrap()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Binary input cannot be given on the command line; pass file paths with --files");
+        let output = run_with_input(&["merge", "--in", "bin", "--compact"], &hex::decode("a2616101616202").unwrap());
+        assert_eq!(output, "{\"a\": 1, \"b\": 2}\n");
+    }
+
+    #[test]
+    fn test_merge_deep() {
+        let base = r#"{"a": {"x": 1, "list": [1, 2]}, "b": [1]}"#;
+        let layer = r#"{"a": {"y": 2, "list": [2, 3]}, "b": 2}"#;
+        let merge = |args: &[&str]| {
+            let mut all_args = vec!["merge", "--in", "json", "--compact"];
+            all_args.extend(args);
+            all_args.extend([base, layer]);
+            run_with_input(&all_args, &[]).trim().to_string()
+        };
+        // A shallow merge replaces the whole map under "a".
+        assert_eq!(merge(&[]), r#"{"a": {"y": 2, "list": [2, 3]}, "b": 2}"#);
+        assert_eq!(merge(&["--deep"]), r#"{"a": {"x": 1, "y": 2, "list": [2, 3]}, "b": 2}"#);
+        assert_eq!(merge(&["--deep", "--merge-arrays", "replace"]), r#"{"a": {"x": 1, "y": 2, "list": [2, 3]}, "b": 2}"#);
+        assert_eq!(merge(&["--deep", "--merge-arrays", "concat"]), r#"{"a": {"x": 1, "y": 2, "list": [1, 2, 2, 3]}, "b": 2}"#);
+        assert_eq!(merge(&["--deep", "--merge-arrays", "union"]), r#"{"a": {"x": 1, "y": 2, "list": [1, 2, 3]}, "b": 2}"#);
+        // Only values that cannot be combined conflict.
+        let result = run(["dcbor", "merge", "--in", "json", "--deep", "--merge-arrays", "concat", "--error-on-conflict", base, layer], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), r#"Key "b" in input 2 is already present in an earlier input"#);
+        let result = run(["dcbor", "merge", "--in", "json", "--deep", "--error-on-conflict", r#"{"a": {"x": 1}}"#, r#"{"a": {"x": 2}}"#], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), r#"Key "x" at $.a in input 2 is already present in an earlier input"#);
+    }
+}
@@ -0,0 +1,147 @@
+pub mod append;
+pub mod build;
+pub mod canonicalize;
+pub mod cose;
+pub mod default;
+pub mod diff;
+pub mod edit;
+pub mod entries;
+pub mod eq;
+pub mod keys;
+pub mod merge;
+pub mod select;
+pub mod selftest;
+pub mod stats;
+pub mod tag;
+pub mod untag;
+pub mod validate;
+pub mod values;
+
+use std::{io::{self, Read, Write}, path::PathBuf};
+
+use anyhow::{Result, bail};
+use clap::Args;
+use dcbor::prelude::*;
+
+use crate::{decode_data, format_output, read_input_data, read_input_file, FormatOptions, InputFormat, OutputFormat};
+
+/// A command that reads its input from `reader`, writes its output to
+/// `writer` and any diagnostics to `errors`, and renders values as the
+/// `options` direct.
+pub trait Exec {
+    fn exec(&self, reader: &mut dyn Read, writer: &mut dyn Write, errors: &mut dyn Write, options: &FormatOptions) -> Result<()>;
+
+    /// Whether the command is set to write raw binary output.
+    fn writes_binary(&self) -> bool {
+        false
+    }
+}
+
+/// The options for commands that read a single dCBOR value.
+#[derive(Args)]
+#[doc(hidden)]
+pub struct InputArgs {
+    /// Input dCBOR as text in the input format (hexadecimal by default). If not provided here or input format is binary, input is read from STDIN
+    pub hex: Option<String>,
+
+    /// The input format
+    #[arg(short, long, value_enum, default_value_t = InputFormat::Hex)]
+    pub r#in: InputFormat,
+}
+
+impl InputArgs {
+    /// Reads the encoded input from the command line, or from `reader`.
+    pub fn read_data(&self, reader: &mut dyn Read, options: &FormatOptions) -> Result<Vec<u8>> {
+        options.timings.time("read input", || read_input_data(self.r#in, self.hex.as_deref(), reader, &options.json_input))
+    }
+
+    /// Reads and decodes the input from the command line, or from `reader`.
+    pub fn read(&self, reader: &mut dyn Read, options: &FormatOptions) -> Result<CBOR> {
+        let data = self.read_data(reader, options)?;
+        options.timings.time("decode", || decode_data(data, false))
+    }
+}
+
+/// The options for commands that output dCBOR values.
+#[derive(Args)]
+#[doc(hidden)]
+pub struct OutputArgs {
+    /// The output format
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Diag)]
+    pub out: OutputFormat,
+
+    /// Output diagnostic notation, hexadecimal, or JSON in compact form. Ignored for other output formats
+    #[arg(short, long, default_value_t = false)]
+    pub compact: bool,
+}
+
+impl OutputArgs {
+    /// Writes `cbor` to `writer` in the output format.
+    pub fn write(&self, cbor: &CBOR, writer: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        writer.write_all(&format_output(cbor, self.out, self.compact, options)?)?;
+        Ok(())
+    }
+
+    pub fn writes_binary(&self) -> bool {
+        self.out == OutputFormat::Bin
+    }
+}
+
+/// The options for commands that read a single dCBOR value and output the
+/// result of an operation on it.
+#[derive(Args)]
+#[doc(hidden)]
+pub struct IoArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+/// The options for commands that compare two dCBOR values.
+#[derive(Args)]
+#[doc(hidden)]
+pub struct PairArgs {
+    /// The left and right inputs as text in the input format, unless read from files with --left/--right
+    #[arg(num_args = 0..=2, value_name = "INPUT")]
+    pub inputs: Vec<String>,
+
+    /// Read the left input from a file
+    #[arg(long, value_name = "PATH")]
+    pub left: Option<PathBuf>,
+
+    /// Read the right input from a file
+    #[arg(long, value_name = "PATH")]
+    pub right: Option<PathBuf>,
+
+    /// The input format
+    #[arg(short, long, value_enum, default_value_t = InputFormat::Hex)]
+    pub r#in: InputFormat,
+}
+
+impl PairArgs {
+    /// Reads and decodes the left and right inputs for `command`, each from
+    /// its file or else from the next positional input.
+    pub fn read(&self, command: &str, options: &FormatOptions) -> Result<(CBOR, CBOR)> {
+        let mut inputs = self.inputs.iter();
+        let left = self.read_side(command, &self.left, &mut inputs, options)?;
+        let right = self.read_side(command, &self.right, &mut inputs, options)?;
+        if inputs.next().is_some() {
+            bail!("{} requires exactly two inputs", command);
+        }
+        Ok((left, right))
+    }
+
+    fn read_side(&self, command: &str, file: &Option<PathBuf>, inputs: &mut std::slice::Iter<'_, String>, options: &FormatOptions) -> Result<CBOR> {
+        let data = match file {
+            Some(path) => options.timings.time("read input", || read_input_file(self.r#in, path, &options.json_input))?,
+            None if self.r#in == InputFormat::Bin => bail!("Binary input cannot be given on the command line; read it from files with --left and --right"),
+            None => match inputs.next() {
+                Some(input) => options.timings.time("read input", || read_input_data(self.r#in, Some(input), &mut io::empty(), &options.json_input))?,
+                None => bail!("{} requires two inputs", command),
+            },
+        };
+        options.timings.time("decode", || decode_data(data, false))
+    }
+}
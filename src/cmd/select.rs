@@ -0,0 +1,188 @@
+use std::io::{Read, Write};
+
+use anyhow::{Result, anyhow, bail};
+use clap::Args;
+use dcbor::prelude::*;
+
+use crate::{cmd::{Exec, IoArgs}, FormatOptions};
+
+/// Output the value at a path such as $.users[0].name
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    /// The path to select, starting at `$`: `.name` looks up a text key, `.N` an unsigned integer key, `[N]` an array element, `["text"]` any text key, and `[-N]`, `[1.5]`, `[h'0102']`, `[true]`, `[false]`, or `[null]` a key of another type
+    #[arg(value_name = "PATH")]
+    path: String,
+
+    #[command(flatten)]
+    io: IoArgs,
+}
+
+/// One step of a path, along with its text as written.
+pub enum Segment {
+    Key(CBOR, String),
+    Index(usize, String),
+}
+
+impl Segment {
+    /// The segment as written in the path, such as `.name` or `[0]`.
+    pub fn text(&self) -> &str {
+        match self {
+            Segment::Key(_, text) | Segment::Index(_, text) => text,
+        }
+    }
+}
+
+pub fn parse_path(path: &str) -> Result<Vec<Segment>> {
+    let mut rest = path.strip_prefix('$').unwrap_or(path);
+    let mut segments = vec!();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let len = after.find(['.', '[']).unwrap_or(after.len());
+            let name = &after[..len];
+            if name.is_empty() {
+                bail!("Empty key in path {}", path);
+            }
+            let key = match name.parse::<u64>() {
+                Ok(value) => CBOR::from(value),
+                Err(_) => CBOR::from(name),
+            };
+            segments.push(Segment::Key(key, format!(".{}", name)));
+            rest = &after[len..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let len = bracket_len(after).ok_or_else(|| anyhow!("Unclosed [ in path {}", path))?;
+            let inner = &after[..len];
+            let text = format!("[{}]", inner);
+            if inner.starts_with('"') {
+                let key: String = serde_json::from_str(inner).map_err(|_| anyhow!("Invalid quoted key {} in path {}", text, path))?;
+                segments.push(Segment::Key(CBOR::from(key), text));
+            } else if let Ok(index) = inner.parse() {
+                segments.push(Segment::Index(index, text));
+            } else {
+                let key = bracket_key(inner).ok_or_else(|| anyhow!("Invalid array index or key {} in path {}", text, path))?;
+                segments.push(Segment::Key(key, text));
+            }
+            rest = &after[len + 1..];
+        } else {
+            bail!("Expected . or [ at \"{}\" in path {}", rest, path);
+        }
+    }
+    Ok(segments)
+}
+
+/// Parses a map key written in brackets in the diagnostic notation that paths
+/// are printed with: a negative integer, a float, a byte string such as
+/// `h'0102'`, or `true`, `false`, or `null`.
+fn bracket_key(inner: &str) -> Option<CBOR> {
+    let key = if let Some(hex) = inner.strip_prefix("h'").and_then(|rest| rest.strip_suffix('\'')) {
+        CBOR::to_byte_string(hex::decode(hex).ok()?)
+    } else if let Some(magnitude) = inner.strip_prefix('-').and_then(|magnitude| magnitude.parse::<u64>().ok()) {
+        // CBOR's negative integers encode -1 - n for an unsigned n.
+        CBORCase::Negative(magnitude.checked_sub(1)?).into()
+    } else {
+        match inner {
+            "true" => CBOR::from(true),
+            "false" => CBOR::from(false),
+            "null" => CBOR::null(),
+            _ => CBOR::from(inner.parse::<f64>().ok()?),
+        }
+    };
+    Some(key)
+}
+
+/// Returns the length of the text before the `]` that closes a bracket,
+/// skipping over any quoted string.
+fn bracket_len(text: &str) -> Option<usize> {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        if in_string {
+            in_string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ']' {
+            return Some(index);
+        }
+    }
+    None
+}
+
+/// Follows `segments` from `cbor`, looking through tags as the other commands
+/// do, and fails naming the first segment that cannot be followed.
+fn resolve(cbor: &CBOR, segments: &[Segment]) -> Result<CBOR> {
+    let mut current = cbor.clone();
+    let mut path = "$".to_string();
+    for segment in segments {
+        while let CBORCase::Tagged(_, item) = current.as_case() {
+            current = item.clone();
+        }
+        current = match (segment, current.as_case()) {
+            (Segment::Key(key, text), CBORCase::Map(map)) => {
+                match map.iter().find(|(candidate, _)| *candidate == key) {
+                    Some((_, value)) => value.clone(),
+                    None => bail!("No key {} at {}", text, path),
+                }
+            },
+            (Segment::Index(index, text), CBORCase::Array(array)) => {
+                match array.get(*index) {
+                    Some(item) => item.clone(),
+                    None => bail!("Index {} is out of range for the array of {} element(s) at {}", text, array.len(), path),
+                }
+            },
+            (Segment::Key(_, text), _) => bail!("Cannot look up {} because the value at {} is not a map", text, path),
+            (Segment::Index(_, text), _) => bail!("Cannot look up {} because the value at {} is not an array", text, path),
+        };
+        path.push_str(segment.text());
+    }
+    Ok(current)
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, reader: &mut dyn Read, writer: &mut dyn Write, _errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        let segments = parse_path(&self.path)?;
+        let cbor = self.io.input.read(reader, options)?;
+        let value = resolve(&cbor, &segments)?;
+        self.io.output.write(&value, writer, options)?;
+        Ok(())
+    }
+
+    fn writes_binary(&self) -> bool {
+        self.io.output.writes_binary()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::test::{run, run_with_input, test_diag};
+
+    #[test]
+    fn test_select_path() {
+        let hex = run_with_input(&["--in", "json", "--out", "hex", "--compact", r#"{"users": [{"name": "Al"}]}"#], &[]);
+        let hex = hex.trim();
+        test_diag(&["select", "$.users[0].name", hex], r#""Al""#);
+        test_diag(&["select", "--compact", ".users", hex], r#"[{"name": "Al"}]"#);
+        test_diag(&["select", "$.1.2", "a101c2a1026161"], r#""a""#);
+        test_diag(&["select", r#"$["x y"]"#, "a163782079187b"], "123");
+        // {1: "b", "1": "a"}
+        test_diag(&["select", "$.1", "a201616261316161"], r#""b""#);
+        test_diag(&["select", r#"$["1"]"#, "a201616261316161"], r#""a""#);
+        // {-1: 1, h'01': 2, true: 3} and {1.5: 4}
+        test_diag(&["select", "$[-1]", "a32001410102f503"], "1");
+        test_diag(&["select", "$[h'01']", "a32001410102f503"], "2");
+        test_diag(&["select", "$[true]", "a32001410102f503"], "3");
+        test_diag(&["select", "$[1.5]", "a1f93e0004"], "4");
+        // The paths that other commands print select what they name.
+        let mut output = Vec::new();
+        assert!(run(["dcbor", "diff", "--format", "side-by-side", "a201616261316161", "a201616261316162"], &mut Cursor::new(vec!()), &mut output).is_err());
+        assert_eq!(String::from_utf8(output).unwrap(), "$[\"1\"]  \"a\"  | \"b\"\n");
+        let mut output = Vec::new();
+        assert!(run(["dcbor", "diff", "--format", "side-by-side", "a32001410102f503", "a32002410102f503"], &mut Cursor::new(vec!()), &mut output).is_err());
+        assert_eq!(String::from_utf8(output).unwrap(), "$[-1]  1  | 2\n");
+        let result = run(["dcbor", "select", "$.users[1]", hex], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Index [1] is out of range for the array of 1 element(s) at $.users");
+        let result = run(["dcbor", "select", "$.users.name", hex], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Cannot look up .name because the value at $.users is not a map");
+    }
+}
@@ -0,0 +1,126 @@
+use std::io::{Cursor, Read, Write};
+
+use anyhow::{Result, bail};
+use clap::Args;
+use dcbor::prelude::*;
+
+use crate::{cmd::{Exec, InputArgs}, decode_data, format_output, json::{self, JsonInput}, pem, read_input, FormatOptions, InputFormat, OutputFormat};
+
+/// Check that the input round-trips through every pair of supported formats
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    #[command(flatten)]
+    input: InputArgs,
+}
+
+/// A format that can both encode and decode a dCBOR value.
+#[derive(Copy, Clone)]
+enum Codec {
+    Hex,
+    Bin,
+    Base64,
+    Base64url,
+    Pem,
+    Json,
+}
+
+const CODECS: [Codec; 6] = [Codec::Hex, Codec::Bin, Codec::Base64, Codec::Base64url, Codec::Pem, Codec::Json];
+
+impl Codec {
+    fn name(&self) -> &'static str {
+        match self {
+            Codec::Hex => "hex",
+            Codec::Bin => "bin",
+            Codec::Base64 => "base64",
+            Codec::Base64url => "base64url",
+            Codec::Pem => "pem",
+            Codec::Json => "json",
+        }
+    }
+
+    /// Whether `cbor` can pass through this format without loss.
+    fn applies_to(&self, cbor: &CBOR) -> bool {
+        match self {
+            Codec::Json => json::is_lossless(cbor),
+            _ => true,
+        }
+    }
+
+    fn encode(&self, cbor: &CBOR, options: &FormatOptions) -> Result<Vec<u8>> {
+        match self {
+            Codec::Hex => format_output(cbor, OutputFormat::Hex, true, options),
+            Codec::Bin => format_output(cbor, OutputFormat::Bin, true, options),
+            Codec::Base64 => format_output(cbor, OutputFormat::Base64, true, options),
+            Codec::Base64url => format_output(cbor, OutputFormat::Base64url, true, options),
+            Codec::Pem => Ok(format!("{}\n", pem::encode("CBOR", &cbor.to_cbor_data())).into_bytes()),
+            Codec::Json => format_output(cbor, OutputFormat::Json, true, options),
+        }
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<CBOR> {
+        match self {
+            Codec::Hex => read_input(InputFormat::Hex, None, &mut Cursor::new(data), &JsonInput::default(), false),
+            Codec::Bin => read_input(InputFormat::Bin, None, &mut Cursor::new(data), &JsonInput::default(), false),
+            Codec::Base64 => read_input(InputFormat::Base64, None, &mut Cursor::new(data), &JsonInput::default(), false),
+            Codec::Base64url => read_input(InputFormat::Base64url, None, &mut Cursor::new(data), &JsonInput::default(), false),
+            Codec::Pem => decode_data(pem::decode(std::str::from_utf8(data)?)?, false),
+            Codec::Json => read_input(InputFormat::Json, None, &mut Cursor::new(data), &JsonInput::default(), false),
+        }
+    }
+}
+
+/// Encodes `cbor` with `from`, converts it through `to`, and checks that
+/// converting back reproduces the same value and the same encoding.
+fn round_trip(cbor: &CBOR, from: Codec, to: Codec, options: &FormatOptions) -> Result<()> {
+    let original = from.encode(cbor, options)?;
+    let converted = to.encode(&from.decode(&original)?, options)?;
+    let value = to.decode(&converted)?;
+    if value.to_cbor_data() != cbor.to_cbor_data() {
+        bail!("decoded value differs from the input: {}", value);
+    }
+    if from.encode(&value, options)? != original {
+        bail!("re-encoded {} differs from the original encoding", from.name());
+    }
+    Ok(())
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, reader: &mut dyn Read, writer: &mut dyn Write, _errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        let cbor = self.input.read(reader, options)?;
+        let mut failures = 0;
+        for from in CODECS {
+            for to in CODECS {
+                if !from.applies_to(&cbor) || !to.applies_to(&cbor) {
+                    writeln!(writer, "{} -> {}: skipped", from.name(), to.name())?;
+                    continue;
+                }
+                match round_trip(&cbor, from, to, options) {
+                    Ok(()) => writeln!(writer, "{} -> {}: ok", from.name(), to.name())?,
+                    Err(error) => {
+                        failures += 1;
+                        writeln!(writer, "{} -> {}: FAILED: {}", from.name(), to.name(), error)?;
+                    },
+                }
+            }
+        }
+        if failures > 0 {
+            bail!("{} round trip(s) failed", failures);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::run_with_input;
+
+    #[test]
+    fn test_selftest() {
+        let output = run_with_input(&["selftest", "a2616101616282f5f6"], &[]);
+        assert_eq!(output.lines().count(), 36);
+        assert!(output.lines().all(|line| line.ends_with(": ok")));
+        let output = run_with_input(&["selftest", "a2016161028201f6"], &[]);
+        assert_eq!(output.lines().filter(|line| line.ends_with(": skipped")).count(), 11);
+    }
+}
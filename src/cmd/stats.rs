@@ -0,0 +1,194 @@
+use std::{collections::BTreeSet, io::{Read, Write}};
+
+use anyhow::Result;
+use clap::{Args, ValueEnum};
+use dcbor::prelude::*;
+
+use crate::{cmd::{Exec, InputArgs}, definite, walk, FormatOptions};
+
+/// Report the size and structure of a dCBOR value
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    #[command(flatten)]
+    input: InputArgs,
+
+    /// The output format
+    #[arg(short, long, value_enum, default_value_t = StatsFormat::Text)]
+    out: StatsFormat,
+
+    /// Also report how many integers are encoded in 1, 2, 3, 5, and 9 bytes
+    #[arg(long)]
+    histogram_widths: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+#[doc(hidden)]
+enum StatsFormat {
+    /// One metric per line
+    Text,
+    /// A JSON object
+    Json,
+}
+
+const MAJOR_TYPES: [&str; 8] = ["unsigned", "negative", "bytes", "text", "array", "map", "tagged", "simple"];
+
+/// The possible encoded sizes of an integer in bytes, including its header.
+const INTEGER_WIDTHS: [usize; 5] = [1, 2, 3, 5, 9];
+
+#[derive(Default)]
+struct Stats {
+    map_entries: usize,
+    array_elements: usize,
+    major_types: [usize; 8],
+    tags: BTreeSet<u64>,
+    integer_widths: [usize; 5],
+}
+
+impl Stats {
+    /// Counts `cbor` and everything nested within it, including map keys.
+    fn tally(&mut self, cbor: &CBOR) {
+        let major = match cbor.as_case() {
+            CBORCase::Unsigned(value) => {
+                self.count_integer(0, *value);
+                0
+            },
+            CBORCase::Negative(value) => {
+                self.count_integer(1, *value);
+                1
+            },
+            CBORCase::ByteString(_) => 2,
+            CBORCase::Text(_) => 3,
+            CBORCase::Array(array) => {
+                self.array_elements += array.len();
+                array.iter().for_each(|item| self.tally(item));
+                4
+            },
+            CBORCase::Map(map) => {
+                self.map_entries += map.len();
+                for (key, value) in map.iter() {
+                    self.tally(key);
+                    self.tally(value);
+                }
+                5
+            },
+            CBORCase::Tagged(tag, item) => {
+                self.tags.insert(tag.value());
+                self.tally(item);
+                6
+            },
+            CBORCase::Simple(_) => 7,
+        };
+        self.major_types[major] += 1;
+    }
+
+    /// Counts an integer by the size of its encoding, which is all header.
+    fn count_integer(&mut self, major: u8, value: u64) {
+        let width = definite::header(major, value).len();
+        if let Some(index) = INTEGER_WIDTHS.iter().position(|&w| w == width) {
+            self.integer_widths[index] += 1;
+        }
+    }
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, reader: &mut dyn Read, writer: &mut dyn Write, _errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        let cbor = self.input.read(reader, options)?;
+        let mut stats = Stats::default();
+        stats.tally(&cbor);
+        let size = cbor.to_cbor_data().len();
+        let depth = walk::depth(&cbor);
+
+        match self.out {
+            StatsFormat::Text => {
+                writeln!(writer, "size: {} bytes", size)?;
+                writeln!(writer, "depth: {}", depth)?;
+                writeln!(writer, "map entries: {}", stats.map_entries)?;
+                writeln!(writer, "array elements: {}", stats.array_elements)?;
+                for (name, count) in MAJOR_TYPES.iter().zip(stats.major_types) {
+                    writeln!(writer, "{}: {}", name, count)?;
+                }
+                let tags: Vec<String> = stats.tags.iter().map(|tag| tag.to_string()).collect();
+                writeln!(writer, "tags: {}", if tags.is_empty() { "none".to_string() } else { tags.join(", ") })?;
+                if self.histogram_widths {
+                    for (width, count) in INTEGER_WIDTHS.iter().zip(stats.integer_widths) {
+                        writeln!(writer, "{}-byte integers: {}", width, count)?;
+                    }
+                }
+            },
+            StatsFormat::Json => {
+                let major_types: serde_json::Map<String, serde_json::Value> = MAJOR_TYPES.iter()
+                    .zip(stats.major_types)
+                    .map(|(name, count)| (name.to_string(), count.into()))
+                    .collect();
+                let mut json = serde_json::json!({
+                    "size": size,
+                    "depth": depth,
+                    "map_entries": stats.map_entries,
+                    "array_elements": stats.array_elements,
+                    "major_types": major_types,
+                    "tags": stats.tags,
+                });
+                if self.histogram_widths {
+                    let widths: serde_json::Map<String, serde_json::Value> = INTEGER_WIDTHS.iter()
+                        .zip(stats.integer_widths)
+                        .map(|(width, count)| (width.to_string(), count.into()))
+                        .collect();
+                    json["integer_widths"] = widths.into();
+                }
+                writeln!(writer, "{}", serde_json::to_string_pretty(&json)?)?;
+            },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::run_with_input;
+    use indoc::indoc;
+
+    #[test]
+    fn test_stats() {
+        let output = run_with_input(&["stats", "a2616101616282c101f6"], &[]);
+        assert_eq!(output, indoc! {"
+            size: 10 bytes
+            depth: 3
+            map entries: 2
+            array elements: 2
+            unsigned: 2
+            negative: 0
+            bytes: 0
+            text: 2
+            array: 1
+            map: 1
+            tagged: 1
+            simple: 1
+            tags: 1
+        "});
+        let output = run_with_input(&["stats", "--out", "json", "01"], &[]);
+        let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(json["size"], 1);
+        assert_eq!(json["major_types"]["unsigned"], 1);
+        assert_eq!(json["tags"], serde_json::json!([]));
+        assert!(json.get("integer_widths").is_none());
+    }
+
+    #[test]
+    fn test_histogram_widths() {
+        // [23, 24, -256, 65536, 4294967296, {1: 1000}]: keys are counted too.
+        let input = "8617181838ff1a000100001b0000000100000000a1011903e8";
+        let output = run_with_input(&["stats", "--histogram-widths", input], &[]);
+        assert!(output.ends_with(indoc! {"
+            tags: none
+            1-byte integers: 2
+            2-byte integers: 2
+            3-byte integers: 1
+            5-byte integers: 1
+            9-byte integers: 1
+        "}));
+        let output = run_with_input(&["stats", "--histogram-widths", "--out", "json", input], &[]);
+        let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(json["integer_widths"], serde_json::json!({"1": 2, "2": 2, "3": 1, "5": 1, "9": 1}));
+    }
+}
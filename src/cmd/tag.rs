@@ -0,0 +1,43 @@
+use std::io::{Read, Write};
+
+use anyhow::Result;
+use clap::Args;
+use dcbor::prelude::*;
+
+use crate::{cmd::{Exec, IoArgs}, FormatOptions};
+
+/// Wrap a dCBOR value in a tag
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    /// The tag number
+    #[arg(value_name = "TAG")]
+    tag: u64,
+
+    #[command(flatten)]
+    io: IoArgs,
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, reader: &mut dyn Read, writer: &mut dyn Write, _errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        let item = self.io.input.read(reader, options)?;
+        let cbor = CBOR::to_tagged_value(self.tag, item);
+        self.io.output.write(&cbor, writer, options)?;
+        Ok(())
+    }
+
+    fn writes_binary(&self) -> bool {
+        self.io.output.writes_binary()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::test_diag;
+
+    #[test]
+    fn test_tag() {
+        test_diag(&["tag", "--compact", "40000", "6568656c6c6f"], r#"40000("hello")"#);
+        test_diag(&["tag", "--in", "json", "--out", "hex", "--compact", "1", "[1, 2]"], "c1820102");
+    }
+}
@@ -0,0 +1,51 @@
+use std::io::{Read, Write};
+
+use anyhow::{Result, bail};
+use clap::Args;
+use dcbor::prelude::*;
+
+use crate::{cmd::{Exec, IoArgs}, FormatOptions};
+
+/// Output the content of a value wrapped in the given tag
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    /// The tag number the input must be wrapped in
+    #[arg(value_name = "TAG")]
+    tag: u64,
+
+    #[command(flatten)]
+    io: IoArgs,
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, reader: &mut dyn Read, writer: &mut dyn Write, _errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        let cbor = self.io.input.read(reader, options)?;
+        let item = match cbor.as_case() {
+            CBORCase::Tagged(tag, item) if tag.value() == self.tag => item,
+            CBORCase::Tagged(tag, _) => bail!("Expected tag {} but found tag {}", self.tag, tag.value()),
+            _ => bail!("Expected tag {} but the input is not a tagged value", self.tag),
+        };
+        self.io.output.write(item, writer, options)?;
+        Ok(())
+    }
+
+    fn writes_binary(&self) -> bool {
+        self.io.output.writes_binary()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::test::{run, test_diag};
+
+    #[test]
+    fn test_untag() {
+        test_diag(&["untag", "--compact", "40000", "d99c406568656c6c6f"], r#""hello""#);
+        let result = run(["dcbor", "untag", "2", "c101"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Expected tag 2 but found tag 1");
+        let result = run(["dcbor", "untag", "2", "01"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Expected tag 2 but the input is not a tagged value");
+    }
+}
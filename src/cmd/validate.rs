@@ -0,0 +1,51 @@
+use std::io::{Read, Write};
+
+use anyhow::{Result, bail};
+use clap::Args;
+
+use crate::{cmd::{Exec, InputArgs}, decode_data, scan, FormatOptions};
+
+/// Check that the input is in canonical dCBOR form, reporting every violation
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    #[command(flatten)]
+    input: InputArgs,
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, reader: &mut dyn Read, writer: &mut dyn Write, _errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        let data = self.input.read_data(reader, options)?;
+
+        let violations = scan::scan_all(&data);
+        for violation in &violations {
+            writeln!(writer, "{}", violation)?;
+        }
+        if !violations.is_empty() {
+            bail!("Input is not canonical dCBOR: {} violation(s) found", violations.len());
+        }
+
+        // Belt and braces: the canonical encoding of the decoded value must
+        // reproduce the input exactly.
+        if decode_data(data.clone(), false)?.to_cbor_data() != data {
+            bail!("Input is not canonical dCBOR: re-encoding the decoded value does not reproduce it");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::test::{run, run_with_input};
+
+    #[test]
+    fn test_validate() {
+        assert!(run_with_input(&["validate", "a2616101616202"], &[]).is_empty());
+        let mut output = Vec::new();
+        // [1.0] encodes a whole-number float rather than the integer 1.
+        let result = run(["dcbor", "validate", "81f93c00"], &mut Cursor::new(vec!()), &mut output);
+        assert_eq!(result.unwrap_err().to_string(), "Input is not canonical dCBOR: 1 violation(s) found");
+        assert!(String::from_utf8(output).unwrap().starts_with("$[0] (byte 1): "));
+    }
+}
@@ -0,0 +1,49 @@
+use std::io::{Read, Write};
+
+use anyhow::{Result, bail};
+use clap::Args;
+use dcbor::prelude::*;
+
+use crate::{cmd::{Exec, IoArgs}, FormatOptions};
+
+/// Output the values of a map or the elements of an array, one per line
+#[derive(Args)]
+#[doc(hidden)]
+pub struct CommandArgs {
+    #[command(flatten)]
+    io: IoArgs,
+}
+
+impl Exec for CommandArgs {
+    fn exec(&self, reader: &mut dyn Read, writer: &mut dyn Write, _errors: &mut dyn Write, options: &FormatOptions) -> Result<()> {
+        let cbor = self.io.input.read(reader, options)?;
+        // dCBOR maps iterate in canonical (encoded byte) order of their keys.
+        let values: Vec<&CBOR> = match cbor.as_case() {
+            CBORCase::Map(map) => map.iter().map(|(_, value)| value).collect(),
+            CBORCase::Array(array) => array.iter().collect(),
+            _ => bail!("values requires a map or an array as input"),
+        };
+        for value in values {
+            self.io.output.write(value, writer, options)?;
+        }
+        Ok(())
+    }
+
+    fn writes_binary(&self) -> bool {
+        self.io.output.writes_binary()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::test::{run, test_diag};
+
+    #[test]
+    fn test_values() {
+        test_diag(&["values", "--compact", "a30161616161820102626b6b01"], "\"a\"\n[1, 2]\n1");
+        test_diag(&["values", "--compact", "83016161f6"], "1\n\"a\"\nnull");
+        let result = run(["dcbor", "values", "01"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "values requires a map or an array as input");
+    }
+}
@@ -0,0 +1,115 @@
+//! Rendering of dCBOR as source code, for embedding values in programs and
+//! tests.
+
+use dcbor::prelude::*;
+
+/// Renders `cbor` as a Rust expression that constructs it with the `dcbor`
+/// prelude. Unless `compact`, arrays and maps are spread over indented lines.
+pub fn rust(cbor: &CBOR, compact: bool) -> String {
+    let mut result = String::new();
+    write_rust(cbor, 0, compact, &mut result);
+    result
+}
+
+fn write_rust(cbor: &CBOR, depth: usize, compact: bool, result: &mut String) {
+    let (open, separator, close) = if compact {
+        (String::new(), " ".to_string(), String::new())
+    } else {
+        (format!("\n{}", "    ".repeat(depth + 1)), format!("\n{}", "    ".repeat(depth + 1)), format!("\n{}", "    ".repeat(depth)))
+    };
+    match cbor.as_case() {
+        CBORCase::Unsigned(value) => result.push_str(&format!("CBOR::from({}u64)", value)),
+        CBORCase::Negative(value) => match i64::try_from(*value) {
+            Ok(value) => result.push_str(&format!("CBOR::from({}i64)", -1 - value)),
+            Err(_) => result.push_str(&format!("CBOR::from(CBORCase::Negative({}))", value)),
+        },
+        CBORCase::ByteString(bytes) => {
+            result.push_str(&format!("CBOR::to_byte_string({})", byte_list(bytes.data(), "[", "]")));
+        },
+        CBORCase::Text(text) => result.push_str(&format!("CBOR::from({:?})", text)),
+        CBORCase::Array(array) if array.is_empty() => result.push_str("CBOR::from(Vec::<CBOR>::new())"),
+        CBORCase::Array(array) => {
+            result.push_str(&format!("CBOR::from(vec![{}", open));
+            for (index, item) in array.iter().enumerate() {
+                if index > 0 {
+                    result.push(',');
+                    result.push_str(&separator);
+                }
+                write_rust(item, depth + 1, compact, result);
+            }
+            result.push_str(&format!("{}])", close));
+        },
+        CBORCase::Map(map) => {
+            result.push_str(&format!("{{{}let mut map = Map::new();", open));
+            for (key, value) in map.iter() {
+                result.push_str(&format!("{}map.insert(", separator));
+                write_rust(key, depth + 1, compact, result);
+                result.push_str(", ");
+                write_rust(value, depth + 1, compact, result);
+                result.push_str(");");
+            }
+            result.push_str(&format!("{}CBOR::from(map){}}}", separator, close));
+        },
+        CBORCase::Tagged(tag, item) => {
+            result.push_str(&format!("CBOR::to_tagged_value({}, ", tag.value()));
+            write_rust(item, depth, compact, result);
+            result.push(')');
+        },
+        CBORCase::Simple(Simple::False) => result.push_str("CBOR::from(false)"),
+        CBORCase::Simple(Simple::True) => result.push_str("CBOR::from(true)"),
+        CBORCase::Simple(Simple::Null) => result.push_str("CBOR::from(CBORCase::Simple(Simple::Null))"),
+        CBORCase::Simple(Simple::Float(value)) => {
+            let value = if value.is_nan() {
+                "f64::NAN".to_string()
+            } else if value.is_infinite() {
+                if *value > 0.0 { "f64::INFINITY" } else { "f64::NEG_INFINITY" }.to_string()
+            } else {
+                format!("{:?}f64", value)
+            };
+            result.push_str(&format!("CBOR::from({})", value));
+        },
+    }
+}
+
+/// Renders `bytes` as comma-separated hexadecimal literals between `open` and
+/// `close`.
+fn byte_list(bytes: &[u8], open: &str, close: &str) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+    format!("{}{}{}", open, bytes.join(", "), close)
+}
+
+/// Like `byte_list`, but unless `compact` puts 16 bytes on each indented line.
+fn byte_rows(bytes: &[u8], open: &str, close: &str, compact: bool) -> String {
+    if compact || bytes.is_empty() {
+        return byte_list(bytes, open, close);
+    }
+    let rows: Vec<String> = bytes.chunks(16).map(|row| format!("    {}", byte_list(row, "", ""))).collect();
+    format!("{}\n{}\n{}", open, rows.join(",\n"), close)
+}
+
+/// Renders `bytes` as a C array initializer, declared as `name` if given.
+pub fn c_array(bytes: &[u8], name: Option<&str>, compact: bool) -> String {
+    let array = byte_rows(bytes, "{", "}", compact);
+    match name {
+        Some(name) => format!("const uint8_t {}[] = {};", name, array),
+        None => array,
+    }
+}
+
+/// Renders `bytes` as a Rust byte slice, declared as `name` if given.
+pub fn rust_bytes(bytes: &[u8], name: Option<&str>, compact: bool) -> String {
+    let slice = byte_rows(bytes, "&[", "]", compact);
+    match name {
+        Some(name) => format!("const {}: &[u8] = {};", name, slice),
+        None => slice,
+    }
+}
+
+/// Renders `bytes` as a Python bytes literal, assigned to `name` if given.
+pub fn python_bytes(bytes: &[u8], name: Option<&str>) -> String {
+    let literal: String = bytes.iter().map(|byte| format!("\\x{:02x}", byte)).collect();
+    match name {
+        Some(name) => format!("{} = b\"{}\"", name, literal),
+        None => format!("b\"{}\"", literal),
+    }
+}
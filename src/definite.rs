@@ -0,0 +1,167 @@
+//! Conversion of indefinite-length CBOR items to their definite-length form,
+//! and of any well-formed CBOR to canonical form, for repairing input from
+//! encoders that do not produce dCBOR.
+
+use anyhow::{Result, anyhow, bail};
+use dcbor::prelude::*;
+
+use crate::scan;
+
+/// Rewrites every indefinite-length string, array, and map in the single CBOR
+/// item at the start of `data` with a definite length, concatenating string
+/// chunks and sorting the keys of indefinite-length maps into canonical order.
+/// Everything else, including any bytes after the item, is copied unchanged
+/// for the dCBOR decoder to check.
+pub fn to_definite(data: &[u8]) -> Result<Vec<u8>> {
+    let (mut result, len) = convert(data, 0, false)?;
+    result.extend_from_slice(&data[len..]);
+    Ok(result)
+}
+
+/// Rewrites the single CBOR item at the start of `data` as `to_definite` does,
+/// and also re-encodes every integer, length, and tag number in its shortest
+/// form, sorts the keys of every map into canonical order, and reduces every
+/// float as dCBOR requires (whole numbers to integers, the shortest exact
+/// width, and a single NaN). What remains for the dCBOR decoder to reject is
+/// what cannot be repaired without changing the value, such as duplicate map
+/// keys.
+pub fn to_canonical(data: &[u8]) -> Result<Vec<u8>> {
+    let (mut result, len) = convert(data, 0, true)?;
+    result.extend_from_slice(&data[len..]);
+    Ok(result)
+}
+
+/// Encodes a minimal-length item header.
+pub fn header(major: u8, value: u64) -> Vec<u8> {
+    let major = major << 5;
+    match value {
+        0..=23 => vec!(major | value as u8),
+        24..=0xff => vec!(major | 24, value as u8),
+        0x100..=0xffff => [vec!(major | 25), (value as u16).to_be_bytes().to_vec()].concat(),
+        0x1_0000..=0xffff_ffff => [vec!(major | 26), (value as u32).to_be_bytes().to_vec()].concat(),
+        _ => [vec!(major | 27), value.to_be_bytes().to_vec()].concat(),
+    }
+}
+
+fn byte_at(data: &[u8], pos: usize) -> Result<u8> {
+    data.get(pos).copied().ok_or_else(|| anyhow!("The input ended at byte offset {} where a data item was expected", pos))
+}
+
+fn is_break(data: &[u8], pos: usize) -> Result<bool> {
+    Ok(byte_at(data, pos)? == 0xff)
+}
+
+/// Returns the argument of the definite-length item header at `pos` and the
+/// number of bytes it occupies after the initial byte.
+pub fn argument(data: &[u8], pos: usize) -> Result<(u64, usize)> {
+    let additional = byte_at(data, pos)? & 0x1f;
+    let len = match additional {
+        0..=23 => return Ok((additional as u64, 0)),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => bail!("Reserved additional information value {} at byte offset {}", additional, pos),
+    };
+    let bytes = data.get(pos + 1..pos + 1 + len)
+        .ok_or_else(|| anyhow!("The input ended inside the item header at byte offset {}", pos))?;
+    Ok((bytes.iter().fold(0u64, |acc, &byte| (acc << 8) | byte as u64), len))
+}
+
+/// Converts the item at `pos`, returning its definite-length encoding (in
+/// canonical form if `canonical`) and the position just after the original
+/// item.
+fn convert(data: &[u8], pos: usize, canonical: bool) -> Result<(Vec<u8>, usize)> {
+    let initial = byte_at(data, pos)?;
+    let major = initial >> 5;
+    let additional = initial & 0x1f;
+    if additional == 31 {
+        let mut end = pos + 1;
+        return match major {
+            2 | 3 => {
+                let mut content = vec!();
+                while !is_break(data, end)? {
+                    let chunk = byte_at(data, end)?;
+                    if chunk >> 5 != major || chunk & 0x1f == 31 {
+                        bail!("Invalid chunk at byte offset {} in an indefinite-length string", end);
+                    }
+                    let (_, chunk_end) = convert(data, end, canonical)?;
+                    let (_, argument_len) = argument(data, end)?;
+                    content.extend_from_slice(&data[end + 1 + argument_len..chunk_end]);
+                    end = chunk_end;
+                }
+                Ok(([header(major, content.len() as u64), content].concat(), end + 1))
+            },
+            4 => {
+                let mut items = vec!();
+                while !is_break(data, end)? {
+                    let (item, item_end) = convert(data, end, canonical)?;
+                    items.push(item);
+                    end = item_end;
+                }
+                Ok(([header(4, items.len() as u64), items.concat()].concat(), end + 1))
+            },
+            5 => {
+                let mut entries = vec!();
+                while !is_break(data, end)? {
+                    let (key, key_end) = convert(data, end, canonical)?;
+                    if is_break(data, key_end)? {
+                        bail!("Indefinite-length map at byte offset {} has a key with no value", pos);
+                    }
+                    let (value, value_end) = convert(data, key_end, canonical)?;
+                    entries.push((key, value));
+                    end = value_end;
+                }
+                entries.sort();
+                let count = entries.len() as u64;
+                let entries: Vec<u8> = entries.into_iter().flat_map(|(key, value)| [key, value].concat()).collect();
+                Ok(([header(5, count), entries].concat(), end + 1))
+            },
+            7 => bail!("Unexpected break at byte offset {}", pos),
+            _ => bail!("Major type {} at byte offset {} cannot have an indefinite length", major, pos),
+        };
+    }
+    let (value, argument_len) = argument(data, pos)?;
+    let content = pos + 1 + argument_len;
+    let head = if canonical { header(major, value) } else { data[pos..content].to_vec() };
+    match major {
+        2 | 3 => {
+            let end = content.checked_add(value as usize).filter(|&end| end <= data.len())
+                .ok_or_else(|| anyhow!("String at byte offset {} extends past the end of the input", pos))?;
+            Ok(([head, data[content..end].to_vec()].concat(), end))
+        },
+        4 | 6 => {
+            let count = if major == 4 { value } else { 1 };
+            let mut result = head;
+            let mut end = content;
+            for _ in 0..count {
+                let (item, item_end) = convert(data, end, canonical)?;
+                result.extend(item);
+                end = item_end;
+            }
+            Ok((result, end))
+        },
+        5 => {
+            value.checked_mul(2).ok_or_else(|| anyhow!("Map at byte offset {} has more entries than can be encoded", pos))?;
+            let mut entries = vec!();
+            let mut end = content;
+            for _ in 0..value {
+                let (key, key_end) = convert(data, end, canonical)?;
+                let (value, value_end) = convert(data, key_end, canonical)?;
+                entries.push((key, value));
+                end = value_end;
+            }
+            if canonical {
+                entries.sort();
+            }
+            let entries: Vec<u8> = entries.into_iter().flat_map(|(key, value)| [key, value].concat()).collect();
+            Ok(([head, entries].concat(), end))
+        },
+        7 if canonical && (25..=27).contains(&additional) => {
+            let value = scan::float_value(&data[pos..content])
+                .ok_or_else(|| anyhow!("Invalid float at byte offset {}", pos))?;
+            Ok((CBOR::from(value).to_cbor_data(), content))
+        },
+        _ => Ok((head, content)),
+    }
+}
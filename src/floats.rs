@@ -0,0 +1,115 @@
+//! Encoding with floats at a fixed width, for test vectors that need a
+//! particular float encoding. The result is not canonical dCBOR unless the
+//! width chosen happens to be the shortest for every float.
+
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use dcbor::prelude::*;
+
+use crate::{definite, scan, walk};
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum FloatWidth {
+    /// The shortest width that represents each float exactly, as dCBOR requires
+    Auto,
+    /// Half precision (2 bytes)
+    #[value(name = "16")]
+    Half,
+    /// Single precision (4 bytes)
+    #[value(name = "32")]
+    Single,
+    /// Double precision (8 bytes)
+    #[value(name = "64")]
+    Double,
+}
+
+/// Encodes `cbor` with every float at `width`, failing if a float cannot be
+/// represented exactly at that width. Whole-number floats are integers in
+/// dCBOR, so they are not affected.
+pub fn encode(cbor: &CBOR, width: FloatWidth) -> Result<Vec<u8>> {
+    let mut data = vec!();
+    encode_at(cbor, width, "$", &mut data)?;
+    Ok(data)
+}
+
+fn encode_at(cbor: &CBOR, width: FloatWidth, path: &str, data: &mut Vec<u8>) -> Result<()> {
+    match cbor.as_case() {
+        CBORCase::Array(array) => {
+            data.extend(definite::header(4, array.len() as u64));
+            for (index, item) in array.iter().enumerate() {
+                encode_at(item, width, &format!("{}[{}]", path, index), data)?;
+            }
+        },
+        CBORCase::Map(map) => {
+            data.extend(definite::header(5, map.len() as u64));
+            for (key, value) in map.iter() {
+                encode_at(key, width, path, data)?;
+                encode_at(value, width, &walk::key_path(path, key), data)?;
+            }
+        },
+        CBORCase::Tagged(tag, item) => {
+            data.extend(definite::header(6, tag.value()));
+            encode_at(item, width, path, data)?;
+        },
+        CBORCase::Simple(Simple::Float(value)) if width != FloatWidth::Auto => {
+            data.extend(encode_float(*value, width).ok_or_else(|| {
+                anyhow!("The float {:?} at {} cannot be encoded in {} bits without loss", value, path, bits(width))
+            })?);
+        },
+        _ => data.extend(cbor.to_cbor_data()),
+    }
+    Ok(())
+}
+
+fn bits(width: FloatWidth) -> usize {
+    match width {
+        FloatWidth::Half => 16,
+        FloatWidth::Single => 32,
+        FloatWidth::Auto | FloatWidth::Double => 64,
+    }
+}
+
+/// Encodes `value` (with its header) at `width` if that loses nothing.
+fn encode_float(value: f64, width: FloatWidth) -> Option<Vec<u8>> {
+    match width {
+        FloatWidth::Half => {
+            let bits = f64_to_f16(value)?;
+            Some([vec!(0xf9), bits.to_be_bytes().to_vec()].concat())
+        },
+        FloatWidth::Single => {
+            let single = value as f32;
+            if !value.is_nan() && single as f64 != value {
+                return None;
+            }
+            let bits = if value.is_nan() { 0x7fc0_0000 } else { single.to_bits() };
+            Some([vec!(0xfa), bits.to_be_bytes().to_vec()].concat())
+        },
+        FloatWidth::Auto | FloatWidth::Double => {
+            let bits = if value.is_nan() { 0x7ff8_0000_0000_0000 } else { value.to_bits() };
+            Some([vec!(0xfb), bits.to_be_bytes().to_vec()].concat())
+        },
+    }
+}
+
+/// The half-precision bits for `value`, if it is exactly representable.
+fn f64_to_f16(value: f64) -> Option<u16> {
+    let sign = if value.is_sign_negative() { 0x8000 } else { 0 };
+    let magnitude = value.abs();
+    let bits = if value.is_nan() {
+        return Some(0x7e00);
+    } else if magnitude == f64::INFINITY {
+        0x7c00
+    } else if magnitude == 0.0 {
+        0
+    } else if !scan::fits_f16(magnitude) {
+        return None;
+    } else if magnitude < 2f64.powi(-14) {
+        (magnitude / 2f64.powi(-24)) as u16
+    } else {
+        let exponent = ((magnitude.to_bits() >> 52) & 0x7ff) as i32 - 1023;
+        let mantissa = ((magnitude / 2f64.powi(exponent) - 1.0) * 1024.0) as u16;
+        ((exponent + 15) as u16) << 10 | mantissa
+    };
+    debug_assert_eq!(scan::f16_to_f64(bits).abs(), magnitude);
+    Some(sign | bits)
+}
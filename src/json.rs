@@ -0,0 +1,323 @@
+//! Conversion between JSON and dCBOR.
+
+use std::{fmt, sync::Mutex};
+
+use anyhow::{Result, bail};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use clap::ValueEnum;
+use dcbor::prelude::*;
+use serde::de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde_json::Value;
+
+use crate::walk;
+
+/// How JSON numbers, which do not distinguish `1` from `1.0`, become dCBOR
+/// integers and floats.
+#[derive(Copy, Clone, PartialEq, Eq, Default, ValueEnum)]
+pub enum JsonNumbers {
+    /// Numbers with whole values become integers however they are written (1, 1.0, 1e2); others become floats
+    #[default]
+    IntWhenIntegral,
+    /// Every number becomes a float; whole numbers that a double cannot hold exactly are rejected
+    AlwaysFloat,
+    /// Numbers written with a decimal point or exponent become floats, rejecting whole numbers that a double cannot hold exactly; others become integers
+    PreserveDecimalPoint,
+}
+
+/// How JSON input given with `--in json` is read, decided by `run_on` from the
+/// global options.
+#[derive(Default)]
+pub struct JsonInput {
+    /// How numbers are converted.
+    pub numbers: JsonNumbers,
+    /// Whether to warn about objects whose keys are not in dCBOR order.
+    pub warn_unsorted: bool,
+    /// The warnings so far, written once the command finishes. Input may be
+    /// read from several threads at once.
+    warnings: Mutex<Vec<String>>,
+}
+
+impl JsonInput {
+    pub fn new(numbers: JsonNumbers, warn_unsorted: bool) -> Self {
+        Self { numbers, warn_unsorted, warnings: Mutex::new(vec!()) }
+    }
+
+    /// Parses JSON text and converts it to dCBOR, noting each object whose keys
+    /// were out of dCBOR order when --warn-unsorted-json is given.
+    pub fn parse(&self, text: &str) -> Result<CBOR> {
+        let cbor = parse(text, self.numbers)?;
+        if self.warn_unsorted {
+            let mut unsorted = vec!();
+            KeyOrder { path: "$".to_string(), unsorted: &mut unsorted }.deserialize(&mut serde_json::Deserializer::from_str(text))?;
+            self.warnings.lock().unwrap_or_else(|error| error.into_inner()).extend(unsorted);
+        }
+        Ok(cbor)
+    }
+
+    /// The warnings in the order they were found.
+    pub fn warnings(self) -> Vec<String> {
+        self.warnings.into_inner().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+/// Walks JSON text in its original order, which `Value` does not keep,
+/// collecting a warning for each object whose keys are not in the order dCBOR
+/// sorts them into: by their encoding, so shorter keys first.
+struct KeyOrder<'a> {
+    path: String,
+    unsorted: &'a mut Vec<String>,
+}
+
+impl<'de> DeserializeSeed<'de> for KeyOrder<'_> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for KeyOrder<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a JSON value")
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let mut index = 0;
+        while seq.next_element_seed(KeyOrder { path: format!("{}[{}]", self.path, index), unsorted: &mut *self.unsorted })?.is_some() {
+            index += 1;
+        }
+        Ok(())
+    }
+
+    // With arbitrary precision, numbers also arrive here, as a map with a
+    // single key, which is never out of order.
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        // Warnings for nested objects follow the one for their parent.
+        let slot = self.unsorted.len();
+        let mut keys = vec!();
+        while let Some(key) = map.next_key::<String>()? {
+            let key = CBOR::from(key.as_str());
+            map.next_value_seed(KeyOrder { path: walk::key_path(&self.path, &key), unsorted: &mut *self.unsorted })?;
+            keys.push(key);
+        }
+        if !keys.windows(2).all(|pair| pair[0].to_cbor_data() <= pair[1].to_cbor_data()) {
+            keys.sort_by_cached_key(|key| key.to_cbor_data());
+            let sorted = keys.iter().map(|key| key.to_string()).collect::<Vec<String>>().join(", ");
+            self.unsorted.insert(slot, format!("JSON object at {} has keys out of dCBOR order, which sorts them as ({})", self.path, sorted));
+        }
+        Ok(())
+    }
+}
+
+/// Converts a JSON value to dCBOR, with numbers converted as `numbers`
+/// directs, and objects as maps with text keys in dCBOR's canonical key
+/// order. Integers outside the range of CBOR integers are rejected rather than
+/// rounded to floats, unless every number is to become a float.
+pub fn to_cbor(value: &Value, numbers: JsonNumbers) -> Result<CBOR> {
+    to_cbor_at(value, "$", numbers)
+}
+
+fn to_cbor_at(value: &Value, path: &str, numbers: JsonNumbers) -> Result<CBOR> {
+    let cbor = match value {
+        Value::Null => CBORCase::Simple(Simple::Null).into(),
+        Value::Bool(value) => (*value).into(),
+        Value::Number(number) => number_to_cbor(number, path, numbers)?,
+        Value::String(text) => text.as_str().into(),
+        Value::Array(array) => array
+            .iter()
+            .enumerate()
+            .map(|(index, item)| to_cbor_at(item, &format!("{}[{}]", path, index), numbers))
+            .collect::<Result<Vec<CBOR>>>()?
+            .into(),
+        Value::Object(object) => {
+            let mut map = Map::new();
+            for (key, value) in object {
+                map.insert(key.as_str(), to_cbor_at(value, &walk::key_path(path, &CBOR::from(key.as_str())), numbers)?);
+            }
+            map.into()
+        },
+    };
+    Ok(cbor)
+}
+
+/// Converts a JSON number from the text it was written as, which
+/// serde_json's `arbitrary_precision` feature keeps.
+fn number_to_cbor(number: &serde_json::Number, path: &str, numbers: JsonNumbers) -> Result<CBOR> {
+    let text = number.to_string();
+    let value = number.as_f64().unwrap_or(f64::NAN);
+    let exact_float = || {
+        if !holds_exactly(&text, value) {
+            bail!("The whole number {} at {} cannot be held exactly by a float", text, path);
+        }
+        Ok(CBOR::from(value))
+    };
+    let written_as_integer = !text.contains(['.', 'e', 'E']);
+    let cbor = match numbers {
+        JsonNumbers::AlwaysFloat => exact_float()?,
+        JsonNumbers::PreserveDecimalPoint if !written_as_integer => exact_float()?,
+        JsonNumbers::IntWhenIntegral if !written_as_integer => integral_value(&text).and_then(integer).unwrap_or_else(|| CBOR::from(value)),
+        _ => match text.parse::<i128>().ok().and_then(integer) {
+            Some(cbor) => cbor,
+            None => bail!("Integer {} at {} is out of the range of CBOR integers", text, path),
+        },
+    };
+    Ok(cbor)
+}
+
+/// Returns `value` as a CBOR integer, if it is within -2^64 to 2^64 - 1.
+fn integer(value: i128) -> Option<CBOR> {
+    if value >= 0 {
+        u64::try_from(value).ok().map(CBOR::from)
+    } else {
+        // CBOR's negative integers encode -1 - n for an unsigned n.
+        u64::try_from(-1 - value).ok().map(|n| CBORCase::Negative(n).into())
+    }
+}
+
+/// Returns the sign, significant digits, and decimal exponent of a JSON
+/// number such as `-12.50e1` (`-`, `125`, `0`) if it is a whole number. Zero
+/// has no digits.
+fn integral_parts(text: &str) -> Option<(bool, String, u64)> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(text) => (true, text),
+        None => (false, text),
+    };
+    let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, exponent.parse::<i64>().ok()?),
+        None => (text, 0),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let mut digits = format!("{}{}", whole, fraction).trim_start_matches('0').to_string();
+    let mut exponent = exponent.checked_sub(fraction.len() as i64)?;
+    while digits.ends_with('0') {
+        digits.pop();
+        exponent += 1;
+    }
+    if digits.is_empty() {
+        return Some((false, digits, 0));
+    }
+    Some((negative, digits, u64::try_from(exponent).ok()?))
+}
+
+/// Returns the exact value of a JSON number such as `-12.50e1` if it is a
+/// whole number of at most 38 digits.
+fn integral_value(text: &str) -> Option<i128> {
+    let (negative, digits, exponent) = integral_parts(text)?;
+    if digits.len() as u64 + exponent > 38 {
+        return None;
+    }
+    let value = format!("0{}{}", digits, "0".repeat(exponent as usize)).parse::<i128>().ok()?;
+    Some(if negative { -value } else { value })
+}
+
+/// Whether `value`, converted from the JSON number `text`, is exact when
+/// `text` is a whole number. A double has 309 digits at most.
+fn holds_exactly(text: &str, value: f64) -> bool {
+    match integral_parts(text) {
+        Some((_, digits, exponent)) if digits.len() as u64 + exponent > 309 => false,
+        Some((_, digits, exponent)) => {
+            let expected = format!("{}{}", digits, "0".repeat(exponent as usize));
+            format!("{:.0}", value.abs()).trim_start_matches('0') == expected
+        },
+        None => true,
+    }
+}
+
+/// Parses JSON text and converts it to dCBOR.
+pub fn parse(text: &str, numbers: JsonNumbers) -> Result<CBOR> {
+    let value: Value = serde_json::from_str(text)?;
+    to_cbor(&value, numbers)
+}
+
+/// Converts dCBOR to a JSON value.
+///
+/// Maps must have text keys. Byte strings become standard (padded) base64
+/// strings, and tagged values become objects of the form
+/// `{"tag": <number>, "value": <content>}`. Non-finite floats and negative
+/// integers below the 64-bit signed range have no JSON representation.
+pub fn from_cbor(cbor: &CBOR) -> Result<Value> {
+    from_cbor_at(cbor, "$")
+}
+
+fn from_cbor_at(cbor: &CBOR, path: &str) -> Result<Value> {
+    let value = match cbor.as_case() {
+        CBORCase::Unsigned(value) => Value::from(*value),
+        CBORCase::Negative(value) => {
+            let Ok(magnitude) = i64::try_from(*value) else {
+                bail!("Negative integer at {} is out of the range representable in JSON", path);
+            };
+            Value::from(-1 - magnitude)
+        },
+        CBORCase::ByteString(bytes) => Value::from(BASE64.encode(bytes)),
+        CBORCase::Text(text) => Value::from(text.as_str()),
+        CBORCase::Array(array) => {
+            let items = array
+                .iter()
+                .enumerate()
+                .map(|(index, item)| from_cbor_at(item, &format!("{}[{}]", path, index)))
+                .collect::<Result<Vec<Value>>>()?;
+            Value::Array(items)
+        },
+        CBORCase::Map(map) => {
+            let mut object = serde_json::Map::new();
+            for (key, value) in map.iter() {
+                let CBORCase::Text(text) = key.as_case() else {
+                    bail!("JSON objects require text keys, but the map at {} has the key {}", path, key);
+                };
+                object.insert(text.clone(), from_cbor_at(value, &walk::key_path(path, key))?);
+            }
+            Value::Object(object)
+        },
+        CBORCase::Tagged(tag, item) => {
+            let mut object = serde_json::Map::new();
+            object.insert("tag".to_string(), Value::from(tag.value()));
+            object.insert("value".to_string(), from_cbor_at(item, path)?);
+            Value::Object(object)
+        },
+        CBORCase::Simple(Simple::False) => Value::Bool(false),
+        CBORCase::Simple(Simple::True) => Value::Bool(true),
+        CBORCase::Simple(Simple::Null) => Value::Null,
+        CBORCase::Simple(Simple::Float(value)) => {
+            let Some(number) = serde_json::Number::from_f64(*value) else {
+                bail!("Float {} at {} has no JSON representation", value, path);
+            };
+            Value::Number(number)
+        },
+    };
+    Ok(value)
+}
+
+/// Returns `true` if `cbor` converts to JSON and back without loss: it has no
+/// byte strings or tags, only text map keys, and only finite floats.
+pub fn is_lossless(cbor: &CBOR) -> bool {
+    match from_cbor(cbor) {
+        Ok(value) => to_cbor(&value, JsonNumbers::default()).is_ok_and(|value| value.to_cbor_data() == cbor.to_cbor_data()),
+        Err(_) => false,
+    }
+}
@@ -1,29 +1,131 @@
 //! A command line tool for parsing and validating Gordian dCBOR. See the main repo [README](https://github.com/BlockchainCommons/bc-dcbor-cli/blob/master/README.md).
 
-use std::{io::{self, Read, Write, BufRead, BufReader}, ffi::OsString};
+mod annotate;
+mod cmd;
+mod codegen;
+mod definite;
+mod floats;
+mod json;
+mod pem;
+mod scan;
+mod tags;
+mod ur;
+mod walk;
+mod yaml;
 
-use clap::{Parser, ValueEnum};
+use std::{io::{self, Read, Write, BufRead, BufReader, IsTerminal}, ffi::OsString, fs::{self, File}, path::{Path, PathBuf}, sync::Mutex, time::{Duration, Instant}};
+
+use clap::{Arg, Command, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use dcbor::prelude::*;
-use anyhow::Result;
+use anyhow::{Result, anyhow, bail};
+use base64::{Engine as _, engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD as BASE64_URL}};
+
+use crate::{cmd::Exec, json::{JsonInput, JsonNumbers}};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
+#[command(after_help = "The DCBOR_IN, DCBOR_OUT, and DCBOR_ANNOTATE environment variables set the defaults of --in, --out, and --annotate-style for every command.")]
 #[doc(hidden)]
 struct Cli {
-    /// Input dCBOR as hexadecimal. If not provided here or input format is binary, input is read from STDIN
-    hex: Option<String>,
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    #[command(flatten)]
+    default: cmd::default::CommandArgs,
+
+    /// Write the output to a file instead of STDOUT. The file is replaced only if the command succeeds
+    #[arg(long, global = true, value_name = "PATH")]
+    output_file: Option<PathBuf>,
+
+    /// Load additional tag names for annotation from a JSON object ({"40500": "my-type"}) or CSV lines (40500,my-type)
+    #[arg(long, global = true, value_name = "PATH")]
+    tags: Option<PathBuf>,
+
+    /// Colorize the comments in annotated diagnostic and hexadecimal output. `auto` colorizes only when writing to a terminal and NO_COLOR is not set
+    #[arg(long, global = true, value_enum, value_name = "WHEN", default_value_t = ColorMode::Auto)]
+    color: ColorMode,
+
+    /// How to annotate diagnostic output. `rich` adds the meaning of values with well-known tags, such as the timestamp of a date; `types` labels every leaf value with its CBOR type instead
+    #[arg(long, global = true, value_enum, value_name = "STYLE", default_value_t = AnnotateStyle::Basic)]
+    annotate_style: AnnotateStyle,
+
+    /// Truncate the comments in annotated hexadecimal output to fit N columns, with an ellipsis. Defaults to the terminal width ($COLUMNS, or 80) when writing to a terminal, and no limit otherwise
+    #[arg(long, global = true, value_name = "N")]
+    width: Option<usize>,
+
+    /// Write binary output even when it would go to a terminal
+    #[arg(long, global = true, default_value_t = false)]
+    force_binary_tty: bool,
 
-    /// The input format
-    #[arg(short, long, value_enum, default_value_t = InputFormat::Hex)]
-    r#in: InputFormat,
+    /// Declare the c-array, rust-bytes, or python-bytes output as a variable with this name
+    #[arg(long, global = true, value_name = "NAME")]
+    var_name: Option<String>,
 
-    /// The output format
-    #[arg(short, long, value_enum, default_value_t = OutputFormat::Diag)]
-    out: OutputFormat,
+    /// Print the time spent reading input, decoding, and formatting output to STDERR on completion
+    #[arg(long, global = true, default_value_t = false)]
+    time: bool,
 
-    /// Output diagnostic notation or hexadecimal in compact form. Ignored for other output formats
-    #[arg(short, long, default_value_t = false)]
-    compact: bool,
+    /// How numbers in JSON input become dCBOR integers and floats. Whole-number floats that fit in 64 bits are still encoded as integers, as dCBOR requires
+    #[arg(long, global = true, value_enum, value_name = "MODE", default_value_t = JsonNumbers::IntWhenIntegral)]
+    json_numbers: JsonNumbers,
+
+    /// Warn about each object in JSON input (--in json) whose keys are not in the order dCBOR sorts them into, shorter keys first
+    #[arg(long, global = true)]
+    warn_unsorted_json: bool,
+
+    /// Render dates (tag 1) in diagnostic output inline as T"1970-01-01T00:00:42Z", in place of 1(42) and its `/ date /` comment. This form is for reading only; diagnostic notation parsers do not accept it
+    #[arg(long, global = true, default_value_t = false)]
+    compact_tags: bool,
+
+    /// The format string for template output. {hex}, {diag}, {size}, and {depth} are replaced with the compact hexadecimal, flat diagnostic notation, encoded length in bytes, and nesting depth of the value; {{ and }} stand for literal braces
+    #[arg(long, global = true, value_name = "FMT")]
+    template: Option<String>,
+
+    /// The type of UR output, such as `date` or `seed`: lowercase letters, digits, and hyphens
+    #[arg(long, global = true, value_name = "TYPE")]
+    ur_type: Option<String>,
+}
+
+#[derive(Subcommand)]
+#[doc(hidden)]
+enum Commands {
+    /// Add elements to the end of an array, or to the start with --prepend
+    Append(cmd::append::CommandArgs),
+    /// Build a dCBOR value from a JSON spec of typed values
+    ///
+    /// Each value in the spec is an object with a `type` of uint, int, float, text, bytes (as hex), bool, null, array (with `items`, a list of values), or map (with `fields`, an object of named values); a `value` for the scalar types; and an optional `tag` number to wrap it in. Problems in the spec are reported with their line and column.
+    Build(cmd::build::CommandArgs),
+    /// Rewrite files as canonical binary dCBOR
+    Canonicalize(cmd::canonicalize::CommandArgs),
+    /// Wrap a dCBOR value as the payload of a COSE_Sign1 skeleton with empty headers and an empty placeholder signature. This shows the structure only; nothing is signed
+    Cose(cmd::cose::CommandArgs),
+    /// Compare two dCBOR values structurally
+    Diff(cmd::diff::CommandArgs),
+    /// Replace or delete the value at a path such as $.users[0].name
+    Edit(cmd::edit::CommandArgs),
+    /// Check that two inputs encode the same dCBOR value, failing if they do not
+    Eq(cmd::eq::CommandArgs),
+    /// Check that the input is in canonical dCBOR form, reporting every violation
+    Validate(cmd::validate::CommandArgs),
+    /// Output the entries of a map, one `key => value` per line, in canonical order
+    Entries(cmd::entries::CommandArgs),
+    /// Output the keys of a map, one per line, in canonical order
+    Keys(cmd::keys::CommandArgs),
+    /// Merge several dCBOR maps into one, with later maps overriding earlier keys
+    Merge(cmd::merge::CommandArgs),
+    /// Output the value at a path such as $.users[0].name
+    Select(cmd::select::CommandArgs),
+    /// Report the size and structure of a dCBOR value
+    Stats(cmd::stats::CommandArgs),
+    /// Wrap a dCBOR value in a tag
+    Tag(cmd::tag::CommandArgs),
+    /// Output the content of a value wrapped in the given tag
+    Untag(cmd::untag::CommandArgs),
+    /// Output the values of a map or the elements of an array, one per line
+    Values(cmd::values::CommandArgs),
+    #[command(hide = true)]
+    Selftest(cmd::selftest::CommandArgs),
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -33,6 +135,16 @@ enum InputFormat {
     Hex,
     /// Raw binary
     Bin,
+    /// JSON, converted to the equivalent dCBOR
+    Json,
+    /// Base64 (standard alphabet, padded)
+    Base64,
+    /// Base64 with the URL-safe alphabet; padding is optional
+    Base64url,
+    /// A PEM block (-----BEGIN label----- ... -----END label-----) with a base64 body; the labels must match
+    Pem,
+    /// Guess the format: hexadecimal if the input is an even number of hex digits (ignoring whitespace, separators, and 0x prefixes), otherwise PEM if it starts with -----BEGIN, otherwise JSON if it starts with {, [, or " and parses as JSON, otherwise raw binary
+    Detect,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -40,89 +152,783 @@ enum InputFormat {
 enum OutputFormat {
     /// CBOR diagnostic notation
     Diag,
+    /// CBOR diagnostic notation on one line without any optional whitespace, e.g. {1:2,3:[4,5]}
+    DiagMinimal,
     /// Hexadecimal
     Hex,
     /// Raw binary
     Bin,
+    /// JSON. Byte strings become base64 strings and tagged values become {"tag": N, "value": ...}; map keys must be text
+    Json,
+    /// Base64 (standard alphabet, padded)
+    Base64,
+    /// Base64 with the URL-safe alphabet, unpadded
+    Base64url,
+    /// A single-part Uniform Resource, ur:<type>/<minimal bytewords>, with the type given by --ur-type
+    Ur,
+    /// YAML, with the same conversions as JSON. Compact YAML is in flow style
+    Yaml,
+    /// A Rust expression that constructs the value with the dcbor prelude
+    Rust,
+    /// The encoded bytes as a C array initializer
+    CArray,
+    /// The encoded bytes as a Rust byte slice
+    RustBytes,
+    /// The encoded bytes as a Python bytes literal
+    PythonBytes,
+    /// The encoded bytes in rows of 16 with offsets and an ASCII column, followed by the annotated hexadecimal breakdown unless compact
+    Hexdump,
+    /// The --template format string, with its placeholders filled in from the value
+    Template,
+    /// The length of the encoding in bytes
+    Size,
     /// No output: merely succeeds on validation of input
     None,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+#[doc(hidden)]
+enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+#[doc(hidden)]
+enum AnnotateStyle {
+    /// Tag names only
+    Basic,
+    /// Tag names and the meaning of the tagged values
+    Rich,
+    /// The CBOR type of every leaf value (uint, nint, bstr, text, bool, null, float), in place of tag names
+    Types,
+}
+
+/// The options that control how values are rendered, decided by `run_on`
+/// from the global options.
+#[doc(hidden)]
+struct FormatOptions {
+    /// The tag names used to annotate output.
+    tags: TagsStore,
+    /// Whether to colorize annotations.
+    color: bool,
+    /// How to annotate diagnostic notation.
+    annotate_style: AnnotateStyle,
+    /// Whether to render dates in diagnostic notation as T"...".
+    compact_tags: bool,
+    /// How JSON input is read.
+    json_input: JsonInput,
+    /// The column limit for annotated hexadecimal output.
+    width: Option<usize>,
+    /// The variable name for source code byte array output.
+    var_name: Option<String>,
+    /// The format string for template output.
+    template: Option<String>,
+    /// The type for UR output.
+    ur_type: Option<String>,
+    /// The time spent in each phase, when --time is given.
+    timings: Timings,
+}
+
+/// The total time spent in each phase, when --time is given. Phases may be
+/// timed from several threads at once.
+#[derive(Default)]
+#[doc(hidden)]
+struct Timings(Option<Mutex<Vec<(&'static str, Duration)>>>);
+
+impl Timings {
+    fn enabled() -> Self {
+        Self(Some(Mutex::new(vec!())))
+    }
+
+    /// Runs `f`, adding the time it takes to the total for `phase`.
+    fn time<T>(&self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let Some(totals) = &self.0 else {
+            return f();
+        };
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        let mut totals = totals.lock().unwrap_or_else(|error| error.into_inner());
+        match totals.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += elapsed,
+            None => totals.push((phase, elapsed)),
+        }
+        result
+    }
+
+    /// The totals in the order the phases were first timed.
+    fn totals(self) -> Vec<(&'static str, Duration)> {
+        self.0.map(|totals| totals.into_inner().unwrap_or_else(|error| error.into_inner())).unwrap_or_default()
+    }
+}
+
+/// Shortens the `# ...` comment on each line of annotated hexadecimal output
+/// that is wider than `width`, ending it with an ellipsis. The hexadecimal
+/// itself is never cut.
 #[doc(hidden)]
-fn read_data<R>(reader: &mut R) -> Result<Vec<u8>> where R: Read {
+fn truncate_comments(hex: &str, width: usize) -> String {
+    hex.split('\n')
+        .map(|line| {
+            let Some(comment) = line.find('#') else {
+                return line.to_string();
+            };
+            if line.chars().count() <= width {
+                return line.to_string();
+            }
+            let keep = width.saturating_sub(1).max(line[..comment].chars().count() + 1);
+            format!("{}…", line.chars().take(keep).collect::<String>())
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+const COMMENT_COLOR: &str = "\x1b[36m";
+const RESET_COLOR: &str = "\x1b[0m";
+
+/// Removes the whitespace outside of strings from flat diagnostic notation,
+/// where it is never significant.
+#[doc(hidden)]
+fn minify(diag: &str) -> String {
+    let mut result = String::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in diag.chars() {
+        if in_string {
+            in_string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+        } else if c == '"' {
+            in_string = true;
+        } else if c.is_whitespace() {
+            continue;
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// Wraps the comments in annotated output in ANSI color codes: `# ...` to the
+/// end of the line in hexadecimal, and `/ ... /` outside of strings in
+/// diagnostic notation (which is also where tag names appear).
+#[doc(hidden)]
+fn colorize(text: &str, format: OutputFormat) -> String {
+    let mut result = String::new();
+    for line in text.split_inclusive('\n') {
+        let (line, newline) = match line.strip_suffix('\n') {
+            Some(line) => (line, "\n"),
+            None => (line, ""),
+        };
+        if format == OutputFormat::Hex {
+            match line.find('#') {
+                Some(index) => result.push_str(&format!("{}{}{}{}", &line[..index], COMMENT_COLOR, &line[index..], RESET_COLOR)),
+                None => result.push_str(line),
+            }
+        } else {
+            let mut in_string = false;
+            let mut in_comment = false;
+            let mut escaped = false;
+            for c in line.chars() {
+                if in_string {
+                    in_string = escaped || c != '"';
+                    escaped = !escaped && c == '\\';
+                } else if in_comment {
+                    result.push(c);
+                    if c == '/' {
+                        result.push_str(RESET_COLOR);
+                        in_comment = false;
+                    }
+                    continue;
+                } else if c == '"' {
+                    in_string = true;
+                } else if c == '/' {
+                    result.push_str(COMMENT_COLOR);
+                    in_comment = true;
+                }
+                result.push(c);
+            }
+            if in_comment {
+                result.push_str(RESET_COLOR);
+            }
+        }
+        result.push_str(newline);
+    }
+    result
+}
+
+/// Renders `data` in the layout of `hexdump -C`: rows of 16 bytes with their
+/// offset and printable ASCII, ending with the total length.
+#[doc(hidden)]
+fn hexdump(data: &[u8]) -> String {
+    let mut lines = vec!();
+    for (row, chunk) in data.chunks(16).enumerate() {
+        let group = |bytes: &[u8]| bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" ");
+        let (first, second) = chunk.split_at(chunk.len().min(8));
+        let ascii: String = chunk.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }).collect();
+        lines.push(format!("{:08x}  {:<23}  {:<23}  |{}|", row * 16, group(first), group(second), ascii));
+    }
+    lines.push(format!("{:08x}", data.len()));
+    lines.join("\n")
+}
+
+/// Fills in the placeholders of `template` from `cbor`. See --template.
+#[doc(hidden)]
+fn render_template(template: &str, cbor: &CBOR) -> Result<String> {
+    let mut result = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                result.push('{');
+            },
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                result.push('}');
+            },
+            '{' => {
+                let rest = chars.as_str();
+                let Some(end) = rest.find('}') else {
+                    bail!("Unclosed placeholder in template: {{{}", rest);
+                };
+                match &rest[..end] {
+                    "hex" => result.push_str(&hex::encode(cbor.to_cbor_data())),
+                    "diag" => result.push_str(&cbor.to_string()),
+                    "size" => result.push_str(&cbor.to_cbor_data().len().to_string()),
+                    "depth" => result.push_str(&walk::depth(cbor).to_string()),
+                    name => bail!("Unknown placeholder in template: {{{}}}", name),
+                }
+                chars = rest[end + 1..].chars();
+            },
+            '}' => bail!("Unmatched }} in template; use }}}} for a literal brace"),
+            _ => result.push(c),
+        }
+    }
+    Ok(result)
+}
+
+#[doc(hidden)]
+fn read_data<R>(reader: &mut R) -> Result<Vec<u8>> where R: Read + ?Sized {
     let mut buf = vec!();
     reader.read_to_end(&mut buf)?;
     Ok(buf)
 }
 
 #[doc(hidden)]
-fn read_string<R>(reader: &mut R) -> Result<String> where R: Read {
+fn read_string<R>(reader: &mut R) -> Result<String> where R: Read + ?Sized {
     let mut reader = BufReader::new(reader);
     let mut result = String::new();
     reader.read_line(&mut result)?;
-    Ok(result)
+    Ok(clean_text(&result))
 }
 
+/// Reads all of `reader` as text input. See `clean_text`.
 #[doc(hidden)]
-fn run<I, T, R, W>(args: I, reader: &mut R, writer: &mut W) -> Result<()>
-where
-    I: IntoIterator<Item = T>,
-    T: Into<OsString> + Clone,
-    R: Read,
-    W: Write
-{
-    let mut known_tags = TagsStore::new([]);
-    known_tags.insert(Tag::new(1, "date"));
+fn read_text<R>(reader: &mut R) -> Result<String> where R: Read + ?Sized {
+    Ok(clean_text(&String::from_utf8(read_data(reader)?)?))
+}
 
-    let cli = Cli::parse_from(args);
+/// Reads the file at `path` as text input. See `clean_text`.
+#[doc(hidden)]
+fn read_text_file(path: &Path) -> Result<String> {
+    Ok(clean_text(&fs::read_to_string(path)?))
+}
+
+/// Strips a leading UTF-8 byte order mark and converts CRLF line endings to
+/// LF, as found in text files saved on Windows.
+#[doc(hidden)]
+fn clean_text(text: &str) -> String {
+    text.strip_prefix('\u{feff}').unwrap_or(text).replace("\r\n", "\n")
+}
+
+/// Renders the bytes leading up to the first decoding problem in `data` as
+/// annotated hex, with a caret against the failing byte. Items that were
+/// complete and valid before the problem are annotated as `--out hex` would
+/// annotate them, inside the headers of the arrays, maps, and tags that
+/// contain the problem.
+#[doc(hidden)]
+fn explain_failure(data: &[u8]) -> String {
+    const CONTEXT_LINES: usize = 16;
+    let (offset, message) = match scan::scan_item(data) {
+        Ok(len) if len < data.len() => (len, "unused data after the end of the item".to_string()),
+        Ok(_) => return "Unable to locate the failing byte".to_string(),
+        Err(error) => (error.offset, error.message),
+    };
+    let hex = |bytes: &[u8]| bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+    // Each row is the indented hex and its comment.
+    let mut rows: Vec<(String, String)> = vec!();
+    let mut depth = 0;
+    let mut pos = 0;
+    while pos < offset {
+        let indent = "   ".repeat(depth);
+        let item = scan::scan_item(&data[pos..offset]).ok()
+            .and_then(|len| CBOR::try_from_data(&data[pos..pos + len]).ok().map(|cbor| (len, cbor)));
+        if let Some((len, cbor)) = item {
+            for line in cbor.hex_opt(true, None).lines() {
+                let (bytes, comment) = line.split_once(" # ").unwrap_or((line, ""));
+                rows.push((format!("{}{}", indent, bytes.trim_end()), comment.to_string()));
+            }
+            pos += len;
+            continue;
+        }
+        // Otherwise the problem is inside this item, so only its header is
+        // shown.
+        let Ok((value, argument_len)) = definite::argument(data, pos) else {
+            break;
+        };
+        let description = match data[pos] >> 5 {
+            4 => format!("array({})", value),
+            5 => format!("map({})", value),
+            6 => format!("tag({})", value),
+            _ => break,
+        };
+        rows.push((format!("{}{}", indent, hex(&data[pos..pos + 1 + argument_len])), description));
+        depth += 1;
+        pos += 1 + argument_len;
+    }
+    let failing = data.get(offset).map(|byte| hex(&[*byte])).unwrap_or_default();
+    rows.push((format!("{}{}", "   ".repeat(depth), failing), "^ decoding failed here".to_string()));
+    if rows.len() > CONTEXT_LINES {
+        rows.drain(..rows.len() - CONTEXT_LINES);
+        rows.insert(0, ("...".to_string(), String::new()));
+    }
+    let width = rows.iter().map(|(bytes, _)| bytes.len()).max().unwrap_or(0);
+    let lines = rows
+        .iter()
+        .map(|(bytes, comment)| if comment.is_empty() { bytes.clone() } else { format!("{:<width$} # {}", bytes, comment) })
+        .collect::<Vec<String>>();
+    format!("Decoding failed at byte offset {}: {}\n{}", offset, message, lines.join("\n"))
+}
 
-    let cbor: CBOR = match (cli.r#in, cli.hex) {
+/// The name, with an article, of the CBOR major type in the high three bits of `byte`.
+#[doc(hidden)]
+fn major_type_name(byte: u8) -> &'static str {
+    match byte >> 5 {
+        0 => "an unsigned integer",
+        1 => "a negative integer",
+        2 => "a byte string",
+        3 => "a text string",
+        4 => "an array",
+        5 => "a map",
+        6 => "a tag",
+        _ => "a simple value or float",
+    }
+}
+
+/// Describes where the first decoding problem in `data` is on one line: its
+/// byte offset, the bytes around it with the failing byte in brackets, and the
+/// major type that byte starts. Returns `None` if the problem can't be found.
+#[doc(hidden)]
+fn locate_failure(data: &[u8]) -> Option<String> {
+    const CONTEXT: usize = 4;
+    let offset = match scan::scan_item(data) {
+        Ok(len) if len < data.len() => len,
+        Ok(_) => return None,
+        Err(error) => error.offset,
+    };
+    let Some(&byte) = data.get(offset) else {
+        return Some(format!("at byte offset {}, the end of the input", offset));
+    };
+    let hex = |bytes: &[u8]| bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<String>>();
+    let mut snippet = hex(&data[offset.saturating_sub(CONTEXT)..offset]);
+    snippet.push(format!("[{:02x}]", byte));
+    snippet.extend(hex(&data[offset + 1..(offset + 1 + CONTEXT).min(data.len())]));
+    Some(format!("at byte offset {} ({}), found {} (major type {})", offset, snippet.join(" "), major_type_name(byte), byte >> 5))
+}
+
+/// Decodes `data`, adding the location of the problem to any error, or with
+/// `explain_error`, the bytes leading up to it.
+#[doc(hidden)]
+fn decode_data(data: Vec<u8>, explain_error: bool) -> Result<CBOR> {
+    match CBOR::try_from_data(&data) {
+        Ok(cbor) => Ok(cbor),
+        Err(error) if explain_error => bail!("{}\n{}", error, explain_failure(&data)),
+        Err(error) => match locate_failure(&data) {
+            Some(location) => bail!("{}\n{}", error, location),
+            None => Err(error.into()),
+        },
+    }
+}
+
+/// Reads the encoded input given as a command line argument, or from `reader`
+/// if there is none or the format is binary. JSON input is converted to dCBOR
+/// and returned encoded.
+#[doc(hidden)]
+fn read_input_data(format: InputFormat, arg: Option<&str>, reader: &mut dyn Read, json_input: &JsonInput) -> Result<Vec<u8>> {
+    match (format, arg) {
         (InputFormat::Hex, Some(hex)) => {
-            CBOR::try_from_hex(&hex)?
+            Ok(hex::decode(clean_hex(hex))?)
         },
         (InputFormat::Hex, None) => {
-            let string = read_string(reader)?;
-            let hex = string.trim();
-            CBOR::try_from_hex(hex)?
+            Ok(hex::decode(clean_hex(&read_text(reader)?))?)
         },
         (InputFormat::Bin, _) => {
-            let data = read_data(reader)?;
-            CBOR::try_from_data(data)?
+            read_data(reader)
+        },
+        (InputFormat::Json, Some(json)) => {
+            Ok(json_input.parse(json)?.to_cbor_data())
+        },
+        (InputFormat::Json, None) => {
+            let text = read_text(reader)?;
+            Ok(json_input.parse(&text)?.to_cbor_data())
+        },
+        (InputFormat::Base64, Some(base64)) => {
+            Ok(BASE64.decode(base64)?)
+        },
+        (InputFormat::Base64, None) => {
+            let string = read_string(reader)?;
+            Ok(BASE64.decode(string.trim())?)
+        },
+        (InputFormat::Base64url, Some(base64)) => {
+            Ok(BASE64_URL.decode(base64.trim_end_matches('='))?)
+        },
+        (InputFormat::Base64url, None) => {
+            let string = read_string(reader)?;
+            Ok(BASE64_URL.decode(string.trim().trim_end_matches('='))?)
+        },
+        (InputFormat::Pem, Some(text)) => {
+            pem::decode(text)
+        },
+        (InputFormat::Pem, None) => {
+            pem::decode(&read_text(reader)?)
+        },
+        (InputFormat::Detect, Some(text)) => {
+            read_detected(text.as_bytes().to_vec(), json_input.numbers)
+        },
+        (InputFormat::Detect, None) => {
+            read_detected(read_data(reader)?, json_input.numbers)
         },
+    }
+}
+
+/// Strips the decoration commonly found around copied hex dumps: whitespace,
+/// `,` and `:` separators, and `0x` prefixes on the whole string or each byte.
+#[doc(hidden)]
+fn clean_hex(text: &str) -> String {
+    text.split(|c: char| c.is_whitespace() || c == ',' || c == ':')
+        .map(|token| token.strip_prefix("0x").or_else(|| token.strip_prefix("0X")).unwrap_or(token))
+        .collect()
+}
+
+/// Decodes `data` in the format guessed from its content, as documented on
+/// `InputFormat::Detect`.
+#[doc(hidden)]
+fn read_detected(data: Vec<u8>, numbers: JsonNumbers) -> Result<Vec<u8>> {
+    let Ok(text) = std::str::from_utf8(&data) else {
+        return Ok(data);
     };
+    let text = &clean_text(text);
+    let hex = clean_hex(text);
+    if !hex.is_empty() && hex.len() % 2 == 0 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(hex::decode(hex)?);
+    }
+    if text.trim_start().starts_with("-----BEGIN ") {
+        return pem::decode(text);
+    }
+    if text.trim_start().starts_with(['{', '[', '"']) {
+        // Binary input can be valid UTF-8 too (0x22 is the integer -3), so
+        // input that does not parse as JSON is taken as binary.
+        if let Ok(cbor) = json::parse(text, numbers) {
+            return Ok(cbor.to_cbor_data());
+        }
+    }
+    Ok(data)
+}
 
-    match cli.out {
+/// Reads the encoded input from the file at `path`. Binary input is read as raw
+/// bytes; other formats are read as UTF-8 text and trimmed.
+#[doc(hidden)]
+fn read_input_file(format: InputFormat, path: &Path, json_input: &JsonInput) -> Result<Vec<u8>> {
+    read_input_bytes(format, fs::read(path)?, json_input)
+}
+
+/// Reads the encoded input from `data`, the contents of a file, as
+/// `read_input_file` does.
+#[doc(hidden)]
+fn read_input_bytes(format: InputFormat, data: Vec<u8>, json_input: &JsonInput) -> Result<Vec<u8>> {
+    match format {
+        InputFormat::Bin => Ok(data),
+        InputFormat::Detect => read_detected(data, json_input.numbers),
+        _ => {
+            let text = clean_text(&String::from_utf8(data)?);
+            read_input_data(format, Some(text.trim()), &mut io::empty(), json_input)
+        },
+    }
+}
+
+/// Reads and decodes the input given as a command line argument, or from
+/// `reader` if there is none or the format is binary.
+#[doc(hidden)]
+fn read_input(format: InputFormat, arg: Option<&str>, reader: &mut dyn Read, json_input: &JsonInput, explain_error: bool) -> Result<CBOR> {
+    decode_data(read_input_data(format, arg, reader, json_input)?, explain_error)
+}
+
+/// Renders `cbor` in the given output format. Text formats end with a newline;
+/// binary output does not.
+#[doc(hidden)]
+fn format_output(cbor: &CBOR, format: OutputFormat, compact: bool, options: &FormatOptions) -> Result<Vec<u8>> {
+    options.timings.time("format output", || render_output(cbor, format, compact, options))
+}
+
+/// Renders `cbor` for `format_output`, untimed.
+#[doc(hidden)]
+fn render_output(cbor: &CBOR, format: OutputFormat, compact: bool, options: &FormatOptions) -> Result<Vec<u8>> {
+    let tags = &options.tags;
+    let output = match format {
         OutputFormat::Diag => {
-            if cli.compact {
-                writer.write_all(format!("{}\n", cbor).as_bytes())?;
+            let compact_dates = |diag: String| if options.compact_tags { annotate::compact_dates(&diag) } else { diag };
+            if compact {
+                format!("{}\n", compact_dates(cbor.to_string())).into_bytes()
             } else {
-                writer.write_all(format!("{}\n", cbor.diagnostic_opt(true, false, false, Some(&known_tags))).as_bytes())?;
+                let diag = match options.annotate_style {
+                    AnnotateStyle::Basic => compact_dates(cbor.diagnostic_opt(true, false, false, Some(tags))),
+                    AnnotateStyle::Rich => annotate::enrich(&compact_dates(cbor.diagnostic_opt(true, false, false, Some(tags)))),
+                    AnnotateStyle::Types => annotate::types(cbor),
+                };
+                let diag = if options.color { colorize(&diag, format) } else { diag };
+                format!("{}\n", diag).into_bytes()
             }
         },
+        OutputFormat::DiagMinimal => {
+            format!("{}\n", minify(&cbor.to_string())).into_bytes()
+        },
         OutputFormat::Hex => {
-            writer.write_all(format!("{}\n", cbor.hex_opt(!cli.compact, Some(&known_tags))).as_bytes())?;
+            let hex = cbor.hex_opt(!compact, Some(tags));
+            let hex = match options.width {
+                Some(width) if !compact => truncate_comments(&hex, width),
+                _ => hex,
+            };
+            let hex = if options.color && !compact { colorize(&hex, format) } else { hex };
+            format!("{}\n", hex).into_bytes()
         },
         OutputFormat::Bin => {
-            writer.write_all(&cbor.to_cbor_data())?;
+            cbor.to_cbor_data()
+        },
+        OutputFormat::Json => {
+            let value = json::from_cbor(cbor)?;
+            let json = if compact { serde_json::to_string(&value)? } else { serde_json::to_string_pretty(&value)? };
+            format!("{}\n", json).into_bytes()
+        },
+        OutputFormat::Yaml => {
+            let value = json::from_cbor(cbor)?;
+            let yaml = if compact { serde_json::to_string(&value)? } else { yaml::to_string(&value) };
+            format!("{}\n", yaml).into_bytes()
+        },
+        OutputFormat::Rust => {
+            format!("{}\n", codegen::rust(cbor, compact)).into_bytes()
         },
-        OutputFormat::None => {},
+        OutputFormat::CArray => {
+            let name = &options.var_name;
+            format!("{}\n", codegen::c_array(&cbor.to_cbor_data(), name.as_deref(), compact)).into_bytes()
+        },
+        OutputFormat::RustBytes => {
+            let name = &options.var_name;
+            format!("{}\n", codegen::rust_bytes(&cbor.to_cbor_data(), name.as_deref(), compact)).into_bytes()
+        },
+        OutputFormat::PythonBytes => {
+            let name = &options.var_name;
+            format!("{}\n", codegen::python_bytes(&cbor.to_cbor_data(), name.as_deref())).into_bytes()
+        },
+        OutputFormat::Hexdump => {
+            let dump = hexdump(&cbor.to_cbor_data());
+            if compact {
+                format!("{}\n", dump).into_bytes()
+            } else {
+                let hex = cbor.hex_opt(true, Some(tags));
+                let hex = match options.width {
+                    Some(width) => truncate_comments(&hex, width),
+                    None => hex,
+                };
+                format!("{}\n\n{}\n", dump, hex).into_bytes()
+            }
+        },
+        OutputFormat::Template => {
+            let Some(template) = &options.template else {
+                bail!("--out template requires --template");
+            };
+            format!("{}\n", render_template(template, cbor)?).into_bytes()
+        },
+        OutputFormat::Size => {
+            format!("{}\n", cbor.to_cbor_data().len()).into_bytes()
+        },
+        OutputFormat::Base64 => {
+            format!("{}\n", BASE64.encode(cbor.to_cbor_data())).into_bytes()
+        },
+        OutputFormat::Base64url => {
+            format!("{}\n", BASE64_URL.encode(cbor.to_cbor_data())).into_bytes()
+        },
+        OutputFormat::Ur => {
+            let Some(ur_type) = &options.ur_type else {
+                bail!("--out ur requires --ur-type");
+            };
+            format!("{}\n", ur::encode(ur_type, &cbor.to_cbor_data())?).into_bytes()
+        },
+        OutputFormat::None => vec!(),
+    };
+    Ok(output)
+}
+
+/// The environment variables that set the default of an option in every
+/// command, with the id of the option.
+const ENV_DEFAULTS: [(&str, &str); 3] = [("DCBOR_IN", "in"), ("DCBOR_OUT", "out"), ("DCBOR_ANNOTATE", "annotate_style")];
+
+/// Builds the command line parser with the defaults given by the variables in
+/// `ENV_DEFAULTS`, looked up with `env`. Explicit options still override them,
+/// and a command whose option doesn't accept the value keeps its own default.
+#[doc(hidden)]
+fn command_with_env_defaults(env: &dyn Fn(&str) -> Option<String>) -> Result<Command> {
+    let mut command = Cli::command();
+    for (variable, id) in ENV_DEFAULTS {
+        let Some(value) = env(variable).filter(|value| !value.is_empty()) else {
+            continue;
+        };
+        let accepts = |arg: &Arg| arg.get_id() == id && arg.get_possible_values().iter().any(|possible| possible.matches(&value, false));
+        if !command.get_arguments().any(accepts) {
+            bail!("{} is set to {}, which is not a valid value for --{}", variable, value, id.replace('_', "-"));
+        }
+        let set_default = |arg: Arg| if accepts(&arg) { arg.default_value(value.clone()) } else { arg };
+        command = command.mut_args(set_default).mut_subcommands(|subcommand| subcommand.mut_args(set_default));
+    }
+    Ok(command)
+}
+
+#[doc(hidden)]
+fn known_tags() -> TagsStore {
+    let mut known_tags = TagsStore::new([]);
+    known_tags.insert(Tag::new(1, "date"));
+    known_tags
+}
+
+/// Runs the tool, looking up environment variables with `env` and writing
+/// diagnostics to `errors`, with `terminal` telling whether `writer` is a
+/// terminal, which changes the defaults for --color and --width and guards
+/// against binary output.
+#[doc(hidden)]
+fn run_on<I, T, R, W, E>(args: I, env: &dyn Fn(&str) -> Option<String>, reader: &mut R, writer: &mut W, errors: &mut E, terminal: bool) -> Result<()>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+    R: Read,
+    W: Write,
+    E: Write
+{
+    let mut known_tags = known_tags();
+
+    let matches = command_with_env_defaults(env)?.get_matches_from(args);
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+
+    let to_terminal = cli.output_file.is_none() && terminal;
+
+    if let Some(path) = &cli.tags {
+        tags::register(&mut known_tags, path, errors)?;
+    }
+
+    let options = FormatOptions {
+        tags: known_tags,
+        color: match cli.color {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => {
+                !matches!(env("NO_COLOR"), Some(value) if !value.is_empty()) && to_terminal
+            },
+        },
+        annotate_style: cli.annotate_style,
+        compact_tags: cli.compact_tags,
+        json_input: JsonInput::new(cli.json_numbers, cli.warn_unsorted_json),
+        width: cli.width.or_else(|| {
+            to_terminal.then(|| env("COLUMNS").and_then(|columns| columns.parse().ok()).unwrap_or(80))
+        }),
+        var_name: cli.var_name.clone(),
+        template: cli.template.clone(),
+        ur_type: cli.ur_type.clone(),
+        timings: if cli.time { Timings::enabled() } else { Timings::default() },
+    };
+
+    let command: &dyn Exec = match &cli.command {
+        Some(Commands::Append(args)) => args,
+        Some(Commands::Build(args)) => args,
+        Some(Commands::Canonicalize(args)) => args,
+        Some(Commands::Cose(args)) => args,
+        Some(Commands::Diff(args)) => args,
+        Some(Commands::Edit(args)) => args,
+        Some(Commands::Eq(args)) => args,
+        Some(Commands::Validate(args)) => args,
+        Some(Commands::Entries(args)) => args,
+        Some(Commands::Keys(args)) => args,
+        Some(Commands::Merge(args)) => args,
+        Some(Commands::Select(args)) => args,
+        Some(Commands::Stats(args)) => args,
+        Some(Commands::Tag(args)) => args,
+        Some(Commands::Untag(args)) => args,
+        Some(Commands::Values(args)) => args,
+        Some(Commands::Selftest(args)) => args,
+        None => &cli.default,
     };
 
-    Ok(())
+    if to_terminal && command.writes_binary() && !cli.force_binary_tty {
+        bail!("Refusing to write binary output to a terminal. Redirect the output, use --output-file, or pass --force-binary-tty");
+    }
+
+    let start = Instant::now();
+    let result = match &cli.output_file {
+        Some(path) => write_output_file(path, |file| command.exec(reader, file, errors, &options)),
+        None => command.exec(reader, writer, errors, &options),
+    };
+    for warning in options.json_input.warnings() {
+        writeln!(errors, "Warning: {}", warning)?;
+    }
+    if cli.time {
+        let total = start.elapsed();
+        for (phase, elapsed) in options.timings.totals() {
+            writeln!(errors, "{}: {:?}", phase, elapsed)?;
+        }
+        writeln!(errors, "total: {:?}", total)?;
+    }
+    result
+}
+
+/// Runs `write` against a temporary file beside `path` and then moves it into
+/// place, so that a command that fails, even after writing part of its output,
+/// leaves an existing file untouched.
+fn write_output_file(path: &Path, write: impl FnOnce(&mut dyn Write) -> Result<()>) -> Result<()> {
+    let name = path.file_name().ok_or_else(|| anyhow!("{} does not name a file", path.display()))?;
+    let temp = path.with_file_name(format!(".{}.{}.tmp", name.to_string_lossy(), std::process::id()));
+    let mut file = File::create(&temp)?;
+    let result = write(&mut file);
+    drop(file);
+    if result.is_ok() {
+        fs::rename(&temp, path)?;
+    } else {
+        fs::remove_file(&temp)?;
+    }
+    result
 }
 
 #[doc(hidden)]
 fn main() -> Result<()> {
-    run(std::env::args_os(), &mut io::stdin(), &mut io::stdout())
+    let terminal = io::stdout().is_terminal();
+    run_on(std::env::args_os(), &|name| std::env::var(name).ok(), &mut io::stdin(), &mut io::stdout(), &mut io::stderr(), terminal)
 }
 
 #[cfg(test)]
 mod test {
-    use std::io::Cursor;
-    use crate::run;
+    use std::{ffi::OsString, io::{self, Cursor, Read, Write}};
+    use anyhow::Result;
+    use crate::{colorize, run_on, ur, OutputFormat};
     use indoc::indoc;
 
-    fn test_diag(args: &[&str], diag: &str) {
+    /// Runs the tool as if `writer` were not a terminal.
+    pub(crate) fn run<I, T, R, W>(args: I, reader: &mut R, writer: &mut W) -> Result<()>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+        R: Read,
+        W: Write
+    {
+        run_on(args, &|_| None, reader, writer, &mut io::sink(), false)
+    }
+
+    pub(crate) fn test_diag(args: &[&str], diag: &str) {
         let mut all_args = vec!["dcbor"];
         all_args.extend(args.iter());
         let mut output: Vec<u8> = Vec::new();
@@ -137,6 +943,15 @@ mod test {
         test_diag(&[hex], diag)
     }
 
+    pub(crate) fn run_with_input(args: &[&str], input: &[u8]) -> String {
+        let mut all_args = vec!["dcbor"];
+        all_args.extend(args.iter());
+        let mut output: Vec<u8> = Vec::new();
+        let mut input_cursor = Cursor::new(input.to_vec());
+        run(all_args, &mut input_cursor, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
     #[test]
     fn test1() {
         test_hex_diag("00", "0");
@@ -157,4 +972,342 @@ mod test {
         "#}.trim();
         test_hex_diag(hex, expected);
     }
+
+    #[test]
+    fn test_pem() {
+        let pem = run_with_input(&["--to-pem", "CBOR", "6548656c6c6f"], &[]);
+        assert_eq!(pem.trim(), "-----BEGIN CBOR-----\nZUhlbGxv\n-----END CBOR-----");
+        let diag = run_with_input(&["--from-pem"], pem.as_bytes());
+        assert_eq!(diag.trim(), r#""Hello""#);
+    }
+
+    #[test]
+    fn test_decode_error_location() {
+        // [1, 2, {2: 1, 1: 2}, 3] has map keys out of order.
+        let result = run(["dcbor", "840102a20201010203"], &mut Cursor::new(vec!()), &mut Vec::new());
+        let message = result.unwrap_err().to_string();
+        assert_eq!(message.lines().nth(1), Some("at byte offset 6 (02 a2 02 01 [01] 02 03), found an unsigned integer (major type 0)"));
+        let result = run(["dcbor", "8201"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string().lines().nth(1), Some("at byte offset 2, the end of the input"));
+    }
+
+    #[test]
+    fn test_json_input() {
+        test_diag(&["--in", "json", "--compact", r#"{"b": [1, 2.5, -3], "a": {"c": null, "d": true}, "e": 1.0}"#], r#"{"a": {"c": null, "d": true}, "b": [1, 2.5, -3], "e": 1}"#);
+        let output = run_with_input(&["--in", "json", "--out", "hex", "--compact"], b"{\"z\": 1,\n\"y\": \"text\"}\n");
+        assert_eq!(output.trim(), "a261796474657874617a01");
+        // Integers beyond the 64-bit signed range keep their exact value.
+        test_diag(&["--in", "json", "--out", "hex", "--compact", "[-9223372036854775809, -18446744073709551616]"], "823b80000000000000003bffffffffffffffff");
+        let result = run(["dcbor", "--in", "json", r#"{"a": [18446744073709551616]}"#], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Integer 18446744073709551616 at $.a[0] is out of the range of CBOR integers");
+        let result = run(["dcbor", "--in", "json", "[-18446744073709551617]"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Integer -18446744073709551617 at $[0] is out of the range of CBOR integers");
+    }
+
+    #[test]
+    fn test_warn_unsorted_json() {
+        let run_json = |args: &[&str]| {
+            let mut all_args = vec!["dcbor", "--in", "json", "--compact"];
+            all_args.extend(args);
+            let (mut output, mut errors) = (Vec::new(), Vec::new());
+            run_on(all_args, &|_| None, &mut Cursor::new(vec!()), &mut output, &mut errors, false).unwrap();
+            (String::from_utf8(output).unwrap(), String::from_utf8(errors).unwrap())
+        };
+        // Shorter keys sort first, and the parent is reported before the objects within it.
+        let (output, errors) = run_json(&["--warn-unsorted-json", r#"{"bb": [{"b": 1, "a": 2}], "c": {"a": 1, "bb": 2}}"#]);
+        assert_eq!(output.trim(), r#"{"c": {"a": 1, "bb": 2}, "bb": [{"a": 2, "b": 1}]}"#);
+        assert_eq!(errors, indoc! {r#"
+            Warning: JSON object at $ has keys out of dCBOR order, which sorts them as ("c", "bb")
+            Warning: JSON object at $.bb[0] has keys out of dCBOR order, which sorts them as ("a", "b")
+        "#});
+        assert_eq!(run_json(&[r#"{"b": 1, "a": 2}"#]).1, "");
+        assert_eq!(run_json(&["--warn-unsorted-json", r#"{"a": 1.5, "bb": [2, 3]}"#]).1, "");
+    }
+
+    #[test]
+    fn test_json_numbers() {
+        // Whole values written as floats are read exactly, beyond a double's precision.
+        test_diag(&["--in", "json", "--compact", "[9007199254740993.0, 1.5e1, -2.50E1, 1e-1]"], "[9007199254740993, 15, -25, 0.1]");
+        test_diag(&["--in", "json", "--compact", "--json-numbers", "preserve-decimal-point", "[9007199254740992.0, 9007199254740993]"], "[9007199254740992, 9007199254740993]");
+        test_diag(&["--in", "json", "--compact", "--json-numbers", "always-float", "[9007199254740992, 1.5]"], "[9007199254740992, 1.5]");
+        // A float cannot hold 2^53 + 1, so these are rejected rather than rounded.
+        let result = run(["dcbor", "--in", "json", "--json-numbers", "always-float", "[1, 9007199254740993]"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "The whole number 9007199254740993 at $[1] cannot be held exactly by a float");
+        let result = run(["dcbor", "--in", "json", "--json-numbers", "preserve-decimal-point", "9007199254740993.0"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "The whole number 9007199254740993.0 at $ cannot be held exactly by a float");
+    }
+
+    #[test]
+    fn test_json_output() {
+        // {"a": h'0102', "b": 1(1614124800), "c": [-1, 1.5, null]}
+        test_diag(&["--out", "json", "--compact", "a361614201026162c11a6035970061638320f93e00f6"], r#"{"a":"AQI=","b":{"tag":1,"value":1614124800},"c":[-1,1.5,null]}"#);
+        let result = run(["dcbor", "--out", "json", "a10102"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "JSON objects require text keys, but the map at $ has the key 1");
+    }
+
+    #[test]
+    fn test_output_file() {
+        let path = std::env::temp_dir().join(format!("dcbor-output-{}.bin", std::process::id()));
+        let output = run_with_input(&["--out", "bin", "--output-file", path.to_str().unwrap(), "820102"], &[]);
+        assert!(output.is_empty());
+        assert_eq!(std::fs::read(&path).unwrap(), vec![0x82, 0x01, 0x02]);
+        run_with_input(&["--out", "hex", "--compact", "--output-file", path.to_str().unwrap(), "820102"], &[]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "820102\n");
+        // A command that fails leaves the existing file as it was.
+        let result = run(["dcbor", "--output-file", path.to_str().unwrap(), "8201"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "820102\n");
+        // So does one that fails after writing part of its output.
+        let result = run(["dcbor", "--sequence", "--out", "hex", "--compact", "--output-file", path.to_str().unwrap(), "018201"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "820102\n");
+        let temp = path.with_file_name(format!(".{}.{}.tmp", path.file_name().unwrap().to_str().unwrap(), std::process::id()));
+        assert!(!temp.exists());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_base64() {
+        test_diag(&["--out", "base64", "8301fb3ff199999999999a6162"], "gwH7P/GZmZmZmZphYg==");
+        test_diag(&["--out", "base64url", "8301fb3ff199999999999a6162"], "gwH7P_GZmZmZmZphYg");
+        test_diag(&["--in", "base64", "--out", "hex", "--compact", "gwH7P/GZmZmZmZphYg=="], "8301fb3ff199999999999a6162");
+        test_diag(&["--in", "base64url", "--out", "hex", "--compact", "gwH7P_GZmZmZmZphYg"], "8301fb3ff199999999999a6162");
+    }
+
+    #[test]
+    fn test_tags_file() {
+        let dir = std::env::temp_dir();
+        let json_path = dir.join(format!("dcbor-tags-{}.json", std::process::id()));
+        let csv_path = dir.join(format!("dcbor-tags-{}.csv", std::process::id()));
+        std::fs::write(&json_path, r#"{"40500": "my-type"}"#).unwrap();
+        std::fs::write(&csv_path, "# custom tags\n40500,my-type\n").unwrap();
+        test_diag(&["--tags", json_path.to_str().unwrap(), "d99e3401"], "40500(1)   / my-type /");
+        test_diag(&["--tags", csv_path.to_str().unwrap(), "d99e3401"], "40500(1)   / my-type /");
+        std::fs::remove_file(json_path).unwrap();
+        std::fs::remove_file(csv_path).unwrap();
+    }
+
+    #[test]
+    fn test_env_defaults() {
+        let run_env = |args: &[&str], env: &[(&str, &str)]| -> Result<String> {
+            let lookup = |name: &str| env.iter().find(|(variable, _)| *variable == name).map(|(_, value)| value.to_string());
+            let mut output = Vec::new();
+            run_on(["dcbor"].iter().chain(args), &lookup, &mut Cursor::new(vec!()), &mut output, &mut io::sink(), false)?;
+            Ok(String::from_utf8(output).unwrap())
+        };
+        let env = [("DCBOR_IN", "json"), ("DCBOR_OUT", "hex")];
+        assert_eq!(run_env(&["--compact", "[1, 2]"], &env).unwrap(), "820102\n");
+        assert_eq!(run_env(&["keys", "--compact", r#"{"a": 1}"#], &env).unwrap(), "6161\n");
+        assert_eq!(run_env(&["merge", "--compact", r#"{"a": 1}"#, r#"{"b": 2}"#], &env).unwrap(), "a2616101616202\n");
+        // Options on the command line override the variables, and commands
+        // whose option doesn't accept the value keep their own default.
+        assert_eq!(run_env(&["--in", "hex", "--out", "diag", "--compact", "820102"], &env).unwrap(), "[1, 2]\n");
+        assert_eq!(run_env(&["entries", r#"{"a": 1}"#], &env).unwrap(), "\"a\" => 1\n");
+        assert_eq!(run_env(&["c11a60359700"], &[("DCBOR_ANNOTATE", "rich")]).unwrap(), "1(1614124800)   / date: 2021-02-24T00:00:00Z /\n");
+        // Unset or empty variables leave the built-in defaults.
+        assert_eq!(run_env(&["c11a60359700"], &[("DCBOR_IN", ""), ("DCBOR_ANNOTATE", "")]).unwrap(), "1(1614124800)   / date /\n");
+        assert_eq!(run_env(&["--compact", "820102"], &[]).unwrap(), "[1, 2]\n");
+        assert_eq!(run_env(&["01"], &[("DCBOR_OUT", "nope")]).unwrap_err().to_string(), "DCBOR_OUT is set to nope, which is not a valid value for --out");
+    }
+
+    #[test]
+    fn test_color() {
+        let output = run_with_input(&["--color", "always", "c101"], &[]);
+        assert_eq!(output, "1(1)   \x1b[36m/ date /\x1b[0m\n");
+        assert_eq!(run_with_input(&["--color", "never", "c101"], &[]), "1(1)   / date /\n");
+        assert_eq!(colorize(r#"["a/b\"/", 1(1)]   / date /"#, OutputFormat::Diag), "[\"a/b\\\"/\", 1(1)]   \x1b[36m/ date /\x1b[0m");
+        assert_eq!(colorize("c1    # tag(1)\n  01", OutputFormat::Hex), "c1    \x1b[36m# tag(1)\x1b[0m\n  01");
+    }
+
+    #[test]
+    fn test_detect() {
+        test_diag(&["--compact", "--in", "detect", "82 01 02"], "[1, 2]");
+        test_diag(&["--compact", "--in", "detect", r#"{"a": 1}"#], r#"{"a": 1}"#);
+        assert_eq!(run_with_input(&["--compact", "--in", "detect"], &[0x82, 0x01, 0x02]), "[1, 2]\n");
+        assert_eq!(run_with_input(&["--compact", "--in", "detect"], b"820102\n"), "[1, 2]\n");
+        // Binary input that looks like the start of JSON is still binary.
+        assert_eq!(run_with_input(&["--in", "detect"], &[0x22]), "-3\n");
+    }
+
+    #[test]
+    fn test_bom_and_crlf() {
+        assert_eq!(run_with_input(&["--compact", "--in", "json"], "\u{feff}{\r\n\"a\": 1\r\n}\r\n".as_bytes()), "{\"a\": 1}\n");
+        assert_eq!(run_with_input(&["--compact"], "\u{feff}82 01\r\n02\r\n".as_bytes()), "[1, 2]\n");
+        let path = std::env::temp_dir().join(format!("dcbor-bom-{}.b64", std::process::id()));
+        std::fs::write(&path, "\u{feff}ggEC\r\n").unwrap();
+        test_diag(&["--compact", "--in", "base64", "--input-file", path.to_str().unwrap()], "[1, 2]");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_compact_tags() {
+        test_diag(&["--compact-tags", "c11a60359700"], r#"T"2021-02-24T00:00:00Z""#);
+        test_diag(&["--compact-tags", "--compact", "82c11a60359700c1fb41d80d65c0200000"], r#"[T"2021-02-24T00:00:00Z", T"2021-02-24T00:00:00.5Z"]"#);
+        // Other tags, and text that looks like a date, are left alone.
+        test_diag(&["--compact-tags", "--compact", "82d5056431283529"], r#"[21(5), "1(5)"]"#);
+        assert_eq!(crate::annotate::compact_dates("[\n    1(42),   / date /\n    2\n]"), "[\n    T\"1970-01-01T00:00:42Z\",\n    2\n]");
+    }
+
+    #[test]
+    fn test_annotate_style() {
+        test_diag(&["c11a60359700"], "1(1614124800)   / date /");
+        test_diag(&["--annotate-style", "rich", "c11a60359700"], "1(1614124800)   / date: 2021-02-24T00:00:00Z /");
+        test_diag(&["--annotate-style", "rich", "c1fb41d80d65c0200000"], "1(1614124800.5)   / date: 2021-02-24T00:00:00.5Z /");
+        assert_eq!(crate::annotate::format_date(-86400.0).unwrap(), "1969-12-31T00:00:00Z");
+        // Fractions are rounded to microseconds, carrying into the seconds.
+        assert_eq!(crate::annotate::format_date(0.9999999).unwrap(), "1970-01-01T00:00:01Z");
+        assert_eq!(crate::annotate::format_date(59.9999999).unwrap(), "1970-01-01T00:01:00Z");
+        assert_eq!(crate::annotate::format_date(1e-7).unwrap(), "1970-01-01T00:00:00Z");
+        assert_eq!(crate::annotate::format_date(1.25).unwrap(), "1970-01-01T00:00:01.25Z");
+        assert_eq!(crate::annotate::format_date(-0.5).unwrap(), "1969-12-31T23:59:59.5Z");
+
+        let hex = run_with_input(&["--in", "json", "--out", "hex", "--compact", r#"{"a": [1, -2, "x", true, null, 1.5, []], "b": {}}"#], &[]);
+        let output = run_with_input(&["--annotate-style", "types", hex.trim()], &[]);
+        assert_eq!(output, indoc! {r#"
+            {
+                "a": [
+                    1,   / uint /
+                    -2,   / nint /
+                    "x",   / text /
+                    true,   / bool /
+                    null,   / null /
+                    1.5,   / float /
+                    []   / array /
+                ],
+                "b": {}   / map /
+            }
+        "#});
+        test_diag(&["--annotate-style", "types", "c1a1014101"], "1({\n    1: h'01'   / bstr /\n})");
+    }
+
+    #[test]
+    fn test_yaml_output() {
+        let json = r#"{"name": "Al", "tags": ["a b", "true", 1.5, []], "nested": [{"x": 1, "y": null}], "empty": {}}"#;
+        let cbor = run_with_input(&["--in", "json", "--out", "hex", "--compact", json], &[]);
+        assert_eq!(run_with_input(&["--out", "yaml", cbor.trim()], &[]), indoc! {r#"
+            empty: {}
+            name: Al
+            nested:
+              - x: 1
+                "y": null
+            tags:
+              - a b
+              - "true"
+              - 1.5
+              - []
+        "#});
+        test_diag(&["--out", "yaml", "--compact", "a1616101"], r#"{"a":1}"#);
+    }
+
+    #[test]
+    fn test_hexdump() {
+        // One-byte heads, then a two-byte head (0x78 0x19) for a 25-byte string
+        // that carries the dump onto a second, partial row.
+        let output = run_with_input(&["--out", "hexdump", "--compact", "8201781948656c6c6f2c2068657864756d7020616c69676e6d656e7421"], &[]);
+        assert_eq!(output, indoc! {"
+            00000000  82 01 78 19 48 65 6c 6c  6f 2c 20 68 65 78 64 75  |..x.Hello, hexdu|
+            00000010  6d 70 20 61 6c 69 67 6e  6d 65 6e 74 21           |mp alignment!|
+            0000001d
+        "});
+        assert_eq!(crate::hexdump(&[]), "00000000");
+    }
+
+    #[test]
+    fn test_width() {
+        let hex = "82                  # array(2)\n   6b               # text(11)\n      48656c6c6f20776f726c64 # \"Hello world\"";
+        assert_eq!(crate::truncate_comments(hex, 30), "82                  # array(2)\n   6b               # text(11)\n      48656c6c6f20776f726c64 #…");
+        assert_eq!(crate::truncate_comments(hex, 36), "82                  # array(2)\n   6b               # text(11)\n      48656c6c6f20776f726c64 # \"Hel…");
+        test_diag(&["--width", "20", "820102"], "[\n    1,\n    2\n]");
+    }
+
+    #[test]
+    fn test_binary_tty_guard() {
+        let result = run_on(["dcbor", "--out", "bin", "01"], &|_| None, &mut Cursor::new(vec!()), &mut Vec::new(), &mut io::sink(), true);
+        assert!(result.unwrap_err().to_string().starts_with("Refusing to write binary output to a terminal"));
+        let mut output = Vec::new();
+        run_on(["dcbor", "--out", "bin", "--force-binary-tty", "01"], &|_| None, &mut Cursor::new(vec!()), &mut output, &mut io::sink(), true).unwrap();
+        assert_eq!(output, [0x01]);
+        let mut output = Vec::new();
+        run_on(["dcbor", "tag", "--out", "hex", "--compact", "1", "01"], &|_| None, &mut Cursor::new(vec!()), &mut output, &mut io::sink(), true).unwrap();
+        assert_eq!(output, b"c101\n");
+    }
+
+    #[test]
+    fn test_rust_output() {
+        let json = r#"{"a": [1, -2, "x\"y"], "b": null}"#;
+        let cbor = run_with_input(&["--in", "json", "--out", "hex", "--compact", json], &[]);
+        assert_eq!(run_with_input(&["--out", "rust", cbor.trim()], &[]), indoc! {r#"
+            {
+                let mut map = Map::new();
+                map.insert(CBOR::from("a"), CBOR::from(vec![
+                    CBOR::from(1u64),
+                    CBOR::from(-2i64),
+                    CBOR::from("x\"y")
+                ]));
+                map.insert(CBOR::from("b"), CBOR::from(CBORCase::Simple(Simple::Null)));
+                CBOR::from(map)
+            }
+        "#});
+        test_diag(&["--out", "rust", "--compact", "c182f93e0042cafe"], "CBOR::to_tagged_value(1, CBOR::from(vec![CBOR::from(1.5f64), CBOR::to_byte_string([0xca, 0xfe])]))");
+    }
+
+    #[test]
+    fn test_byte_array_output() {
+        test_diag(&["--out", "c-array", "--compact", "182a"], "{0x18, 0x2a}");
+        test_diag(&["--out", "c-array", "--var-name", "answer", "182a"], "const uint8_t answer[] = {\n    0x18, 0x2a\n};");
+        test_diag(&["--out", "rust-bytes", "--compact", "--var-name", "ANSWER", "182a"], "const ANSWER: &[u8] = &[0x18, 0x2a];");
+        test_diag(&["--out", "python-bytes", "182a"], r#"b"\x18\x2a""#);
+    }
+
+    #[test]
+    fn test_time() {
+        // Timings go to STDERR, leaving the output unchanged.
+        assert_eq!(run_with_input(&["--time", "--compact", "820102"], &[]), "[1, 2]\n");
+    }
+
+    #[test]
+    fn test_ur_output() {
+        // The test vector from BCR-2020-012, ending with its CRC-32 checksum.
+        assert_eq!(ur::bytewords_minimal(&[0, 1, 2, 128, 255]), "aeadaolazmjendeoti");
+        test_diag(&["--out", "ur", "--ur-type", "test", "8102"], "ur:test/lyaolggwtnta");
+        let result = run(["dcbor", "--out", "ur", "01"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "--out ur requires --ur-type");
+        let result = run(["dcbor", "--out", "ur", "--ur-type", "My_Type", "01"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Invalid UR type \"My_Type\"; it may contain only lowercase letters, digits, and hyphens");
+    }
+
+    #[test]
+    fn test_template_output() {
+        test_diag(&["--out", "template", "--template", "{size} bytes, depth {depth}: {hex} = {diag}", "a1616182f5f6"], r#"6 bytes, depth 2: a1616182f5f6 = {"a": [true, null]}"#);
+        test_diag(&["--out", "template", "--template", "{{{size}}}", "01"], "{1}");
+        let result = run(["dcbor", "--out", "template", "--template", "{nope}", "01"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Unknown placeholder in template: {nope}");
+        let result = run(["dcbor", "--out", "template", "01"], &mut Cursor::new(vec!()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "--out template requires --template");
+    }
+
+    #[test]
+    fn test_size_output() {
+        test_diag(&["--out", "size", "a1616182f5f6"], "6");
+        test_diag(&["--out", "size", "--in", "json", r#"{"a": [true, null]}"#], "6");
+        test_diag(&["--out", "size", "--in", "base64", "AQ=="], "1");
+    }
+
+    #[test]
+    fn test_diag_minimal() {
+        // {1: 2, 3: [4, 5], "a b": "c, d \" e"}
+        let output = run_with_input(&["--out", "diag-minimal", "a30102038204056361206268632c206420222065"], &[]);
+        assert_eq!(output, "{1:2,3:[4,5],\"a b\":\"c, d \\\" e\"}\n");
+        test_diag(&["--out", "diag-minimal", "c1a1614140"], r#"1({"A":h''})"#);
+    }
+
+    #[test]
+    fn test_pem_input() {
+        let armored = "-----BEGIN CBOR-----\noWFhAQ==\n-----END CBOR-----\n";
+        assert_eq!(run_with_input(&["--in", "pem", "--compact"], armored.as_bytes()), "{\"a\": 1}\n");
+        assert_eq!(run_with_input(&["keys", "--in", "pem", "--compact"], armored.as_bytes()), "\"a\"\n");
+        assert_eq!(run_with_input(&["--in", "detect", "--compact"], armored.as_bytes()), "{\"a\": 1}\n");
+        let result = run(["dcbor", "--in", "pem"], &mut Cursor::new(b"-----BEGIN CBOR-----\noWFhAQ==\n-----END DATA-----".to_vec()), &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "PEM labels do not match: BEGIN CBOR but END DATA");
+    }
 }
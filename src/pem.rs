@@ -0,0 +1,35 @@
+//! Minimal PEM (RFC 7468) armoring for encoded dCBOR.
+
+use anyhow::{Result, anyhow, bail};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+
+/// Wraps `data` in a PEM block with the given label.
+pub fn encode(label: &str, data: &[u8]) -> String {
+    let body = BASE64.encode(data);
+    let mut lines = vec!(format!("-----BEGIN {}-----", label));
+    // PEM bodies are wrapped at 64 characters per line (RFC 7468).
+    lines.extend(body.as_bytes().chunks(64).map(|chunk| String::from_utf8_lossy(chunk).into_owned()));
+    lines.push(format!("-----END {}-----", label));
+    lines.join("\n")
+}
+
+/// Validates the BEGIN/END lines of a PEM block and returns its decoded body.
+pub fn decode(text: &str) -> Result<Vec<u8>> {
+    let lines: Vec<&str> = text.lines().map(|line| line.trim()).filter(|line| !line.is_empty()).collect();
+    if lines.len() < 2 {
+        bail!("PEM block must have BEGIN and END lines");
+    }
+    let begin = lines[0]
+        .strip_prefix("-----BEGIN ")
+        .and_then(|rest| rest.strip_suffix("-----"))
+        .ok_or_else(|| anyhow!("Missing PEM BEGIN line"))?;
+    let end = lines[lines.len() - 1]
+        .strip_prefix("-----END ")
+        .and_then(|rest| rest.strip_suffix("-----"))
+        .ok_or_else(|| anyhow!("Missing PEM END line"))?;
+    if begin != end {
+        bail!("PEM labels do not match: BEGIN {} but END {}", begin, end);
+    }
+    let body = lines[1..lines.len() - 1].concat();
+    BASE64.decode(body).map_err(|e| anyhow!("Invalid PEM body: {}", e))
+}
@@ -0,0 +1,470 @@
+//! A structural walker over encoded dCBOR that locates the bytes at which
+//! decoding fails, for use in error reporting and conformance audits.
+
+use std::io::Read;
+
+use anyhow::{Result, anyhow};
+use dcbor::prelude::*;
+
+use crate::walk;
+
+/// The position and description of the first problem found in encoded dCBOR.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanError {
+    pub offset: usize,
+    pub message: String,
+    /// Whether the problem is only that the input ended too soon, so that more
+    /// input could resolve it.
+    pub truncated: bool,
+}
+
+impl ScanError {
+    fn new(offset: usize, message: impl Into<String>) -> Self {
+        Self { offset, message: message.into(), truncated: false }
+    }
+
+    fn truncated(offset: usize, message: impl Into<String>) -> Self {
+        Self { offset, message: message.into(), truncated: true }
+    }
+}
+
+/// A conformance problem found in encoded dCBOR, along with the path (as used
+/// by the other commands, rooted at `$`) of the item containing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub offset: usize,
+    pub path: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (byte {}): {}", self.path, self.offset, self.message)
+    }
+}
+
+/// Walks the single dCBOR item at the start of `data` and returns its encoded
+/// length in bytes, or the offset and reason of the first violation found.
+///
+/// Trailing bytes after the item are not examined.
+pub fn scan_item(data: &[u8]) -> Result<usize, ScanError> {
+    Scanner { data, violations: None }.scan(0, "$")
+}
+
+/// Walks `data` as a single dCBOR item and returns every violation found.
+///
+/// Problems that leave the structure intact (such as misordered map keys or
+/// non-shortest integers) are recorded and the walk continues; problems that
+/// make the rest of the input unreadable (such as truncation) end it.
+pub fn scan_all(data: &[u8]) -> Vec<Violation> {
+    let mut scanner = Scanner { data, violations: Some(vec!()) };
+    let result = scanner.scan(0, "$");
+    let mut violations = scanner.violations.unwrap_or_default();
+    match result {
+        Ok(len) if len < data.len() => {
+            violations.push(Violation { offset: len, path: "$".to_string(), message: "unused data after the end of the item".to_string() });
+        },
+        Ok(_) => {},
+        Err(error) => {
+            violations.push(Violation { offset: error.offset, path: "$".to_string(), message: error.message });
+        },
+    }
+    violations
+}
+
+/// Returns the nesting depth of the item at the start of `data`, as
+/// `walk::depth` would for the decoded value, including any indefinite-length
+/// arrays and maps. The walk keeps its own stack rather than recursing, so it
+/// can guard the recursive decoder against deeply nested input. It stops at
+/// the first malformed header or the end of `data`, leaving the problem for
+/// the decoder to report.
+pub fn depth(data: &[u8]) -> usize {
+    // The number of items still expected in each open array, map, or tag, or
+    // `None` for an indefinite-length array or map, and whether it counts
+    // towards the depth (the chunks of an indefinite-length string do not).
+    let mut open: Vec<(Option<u64>, bool)> = vec!();
+    let mut depth = 0;
+    let mut max_depth = 0;
+    let mut pos = 0;
+    loop {
+        let Some(&initial) = data.get(pos) else {
+            return max_depth;
+        };
+        if initial == 0xff && matches!(open.last(), Some((None, _))) {
+            if let Some((_, true)) = open.pop() {
+                depth -= 1;
+            }
+            pos += 1;
+        } else {
+            let (major, additional) = (initial >> 5, initial & 0x1f);
+            let argument_len = match additional {
+                0..=23 | 31 => 0,
+                24 => 1,
+                25 => 2,
+                26 => 4,
+                27 => 8,
+                _ => return max_depth,
+            };
+            let Some(argument) = data.get(pos + 1..pos + 1 + argument_len) else {
+                return max_depth;
+            };
+            let value = if argument_len == 0 {
+                additional as u64
+            } else {
+                argument.iter().fold(0u64, |acc, &byte| (acc << 8) | byte as u64)
+            };
+            pos += 1 + argument_len;
+            let children = match (major, additional) {
+                (2 | 3, 31) => Some((None, false)),
+                (4 | 5, 31) => Some((None, true)),
+                (2 | 3, _) => {
+                    pos = pos.saturating_add(value.try_into().unwrap_or(usize::MAX));
+                    None
+                },
+                (4, _) => Some((Some(value), true)),
+                (5, _) => Some((Some(value.saturating_mul(2)), true)),
+                (6, _) => Some((Some(1), true)),
+                (7, 31) => return max_depth,
+                _ => None,
+            };
+            if let Some((count, counts)) = children {
+                if counts {
+                    depth += 1;
+                    max_depth = max_depth.max(depth);
+                }
+                if count != Some(0) {
+                    open.push((count, counts));
+                    continue;
+                }
+                if counts {
+                    depth -= 1;
+                }
+            }
+        }
+        // An item is complete, which may complete the items that contain it.
+        loop {
+            match open.last_mut() {
+                None => return max_depth,
+                Some((Some(count), counts)) => {
+                    *count -= 1;
+                    if *count > 0 {
+                        break;
+                    }
+                    if *counts {
+                        depth -= 1;
+                    }
+                    open.pop();
+                },
+                Some((None, _)) => break,
+            }
+        }
+    }
+}
+
+/// The number of bytes requested from the underlying reader at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Reads the items of a CBOR sequence (RFC 8742) incrementally, so that only
+/// the item being read (and at most one chunk beyond it) is held in memory.
+///
+/// Yields each item's byte offset and encoding. Only structural problems that
+/// make the item boundaries unknowable, and items nested more than
+/// `max_depth` deep, are reported here; the items themselves still need
+/// decoding.
+pub struct SequenceReader<R: Read> {
+    reader: R,
+    max_depth: usize,
+    buffer: Vec<u8>,
+    /// The position in `buffer` of the next item.
+    start: usize,
+    /// The byte offset in the whole sequence of `buffer[0]`.
+    offset: usize,
+    eof: bool,
+}
+
+impl<R: Read> SequenceReader<R> {
+    pub fn new(reader: R, max_depth: usize) -> Self {
+        Self { reader, max_depth, buffer: vec!(), start: 0, offset: 0, eof: false }
+    }
+
+    /// Reads more input, discarding the items already yielded. Makes a single
+    /// read, so that items already available are yielded without waiting for
+    /// more input, but offers room for at least as many bytes as are pending
+    /// so that a large item is rescanned fewer times.
+    fn fill(&mut self) -> std::io::Result<()> {
+        self.buffer.drain(..self.start);
+        self.offset += self.start;
+        self.start = 0;
+        let len = self.buffer.len();
+        self.buffer.resize(len + len.max(CHUNK_SIZE), 0);
+        let result = loop {
+            match self.reader.read(&mut self.buffer[len..]) {
+                Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+                result => break result,
+            }
+        };
+        self.buffer.truncate(len + *result.as_ref().unwrap_or(&0));
+        if result? == 0 {
+            self.eof = true;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for SequenceReader<R> {
+    type Item = Result<(usize, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let pending = &self.buffer[self.start..];
+            if !pending.is_empty() {
+                // The scanner recurses, so the depth is checked first. Only
+                // part of the item may have been read, so the depth found so
+                // far is a lower bound.
+                if depth(pending) > self.max_depth {
+                    let offset = self.offset + self.start;
+                    self.start = self.buffer.len();
+                    self.eof = true;
+                    return Some(Err(anyhow!("Sequence item at byte offset {} is nested more than the maximum depth of {}", offset, self.max_depth)));
+                }
+                let mut scanner = Scanner { data: pending, violations: Some(vec!()) };
+                match scanner.scan(0, "$") {
+                    Ok(len) => {
+                        let item = (self.offset + self.start, pending[..len].to_vec());
+                        self.start += len;
+                        return Some(Ok(item));
+                    },
+                    Err(error) if self.eof || !error.truncated => {
+                        let offset = self.offset + self.start + error.offset;
+                        self.start = self.buffer.len();
+                        self.eof = true;
+                        return Some(Err(anyhow!("Invalid CBOR sequence at byte offset {}: {}", offset, error.message)));
+                    },
+                    Err(_) => {},
+                }
+            } else if self.eof {
+                return None;
+            }
+            if let Err(error) = self.fill() {
+                self.eof = true;
+                self.start = self.buffer.len();
+                return Some(Err(error.into()));
+            }
+        }
+    }
+}
+
+/// Walks `data` as a single dCBOR item and returns the violations found in its
+/// floating-point values.
+pub fn float_violations(data: &[u8]) -> Vec<Violation> {
+    scan_all(data)
+        .into_iter()
+        .filter(|violation| matches!(data.get(violation.offset), Some(0xf9..=0xfb)))
+        .collect()
+}
+
+/// Decodes a half-precision float.
+pub fn f16_to_f64(bits: u16) -> f64 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f64;
+    sign * match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        0x1f if mantissa == 0.0 => f64::INFINITY,
+        0x1f => f64::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f64.powi(exponent - 15),
+    }
+}
+
+/// Whether `value` (finite and non-zero) is exactly representable as a
+/// half-precision float.
+pub fn fits_f16(value: f64) -> bool {
+    let magnitude = value.abs();
+    if !(2f64.powi(-24)..=65504.0).contains(&magnitude) {
+        return false;
+    }
+    // Half-precision values have 10 explicit mantissa bits, at an exponent of
+    // no less than -14 (below which they are subnormal).
+    let exponent = (((magnitude.to_bits() >> 52) & 0x7ff) as i32 - 1023).max(-14);
+    (magnitude / 2f64.powi(exponent - 10)).fract() == 0.0
+}
+
+/// Decodes the encoded float `item` (header included) at any width.
+pub fn float_value(item: &[u8]) -> Option<f64> {
+    let value = match item {
+        [0xf9, bytes @ ..] => f16_to_f64(u16::from_be_bytes(bytes.try_into().ok()?)),
+        [0xfa, bytes @ ..] => f32::from_be_bytes(bytes.try_into().ok()?) as f64,
+        [0xfb, bytes @ ..] => f64::from_be_bytes(bytes.try_into().ok()?),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Describes why the encoded float `item` (header included) is not in dCBOR's
+/// canonical form, if it is not.
+fn float_problem(item: &[u8]) -> Option<String> {
+    let value = float_value(item)?;
+    if value.is_nan() {
+        return (item != [0xf9, 0x7e, 0x00]).then(|| "NaN must be encoded as the half-precision value f97e00".to_string());
+    }
+    if value.fract() == 0.0 && value >= -(2f64.powi(64)) && value < 2f64.powi(64) {
+        return Some(format!("the whole-number float {:?} must be encoded as an integer", value));
+    }
+    let width = if value.is_infinite() || fits_f16(value) {
+        3
+    } else if (value as f32) as f64 == value {
+        5
+    } else {
+        9
+    };
+    (item.len() > width).then(|| format!("the float {:?} must be encoded in {} bytes, not {}", value, width, item.len()))
+}
+
+struct Header {
+    major: u8,
+    additional: u8,
+    value: u64,
+    len: usize,
+    minimal: bool,
+}
+
+struct Scanner<'a> {
+    data: &'a [u8],
+    /// Recoverable violations, when collecting them rather than failing fast.
+    violations: Option<Vec<Violation>>,
+}
+
+impl Scanner<'_> {
+    /// Records a recoverable violation, or fails with it when not collecting.
+    fn violation(&mut self, offset: usize, path: &str, message: impl Into<String>) -> Result<(), ScanError> {
+        match &mut self.violations {
+            Some(violations) => {
+                violations.push(Violation { offset, path: path.to_string(), message: message.into() });
+                Ok(())
+            },
+            None => Err(ScanError::new(offset, message)),
+        }
+    }
+
+    fn header(&self, pos: usize) -> Result<Header, ScanError> {
+        let Some(&initial) = self.data.get(pos) else {
+            return Err(ScanError::truncated(pos, "expected a data item but the input ended"));
+        };
+        let major = initial >> 5;
+        let additional = initial & 0x1f;
+        let argument_len = match additional {
+            0..=23 => 0,
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            28..=30 => return Err(ScanError::new(pos, format!("reserved additional information value {}", additional))),
+            _ => return Err(ScanError::new(pos, "indefinite-length items are not allowed in dCBOR")),
+        };
+        let Some(argument) = self.data.get(pos + 1..pos + 1 + argument_len) else {
+            return Err(ScanError::truncated(pos, "the input ended inside an item header"));
+        };
+        let value = if argument_len == 0 {
+            additional as u64
+        } else {
+            argument.iter().fold(0u64, |acc, &byte| (acc << 8) | byte as u64)
+        };
+        // Simple values and floats use the argument bytes differently, so only
+        // integer-like arguments are checked for the shortest form here.
+        let minimal = major == 7 || argument_len == match value {
+            0..=23 => 0,
+            24..=0xff => 1,
+            0x100..=0xffff => 2,
+            0x1_0000..=0xffff_ffff => 4,
+            _ => 8,
+        };
+        Ok(Header { major, additional, value, len: 1 + argument_len, minimal })
+    }
+
+    /// Decodes a leaf item on its own so the library's checks apply to it.
+    fn check_leaf(&mut self, pos: usize, end: usize, path: &str) -> Result<usize, ScanError> {
+        if let Err(error) = CBOR::try_from_data(&self.data[pos..end]) {
+            self.violation(pos, path, error.to_string())?;
+        }
+        Ok(end)
+    }
+
+    fn scan(&mut self, pos: usize, path: &str) -> Result<usize, ScanError> {
+        let header = self.header(pos)?;
+        if !header.minimal {
+            self.violation(pos, path, "non-canonical (non-shortest) encoding of an integer or length")?;
+        }
+        let content = pos + header.len;
+        match header.major {
+            0 | 1 => {
+                if header.minimal {
+                    self.check_leaf(pos, content, path)
+                } else {
+                    Ok(content)
+                }
+            },
+            2 | 3 => {
+                let end = content.checked_add(header.value as usize).filter(|&end| end <= self.data.len());
+                let Some(end) = end else {
+                    return Err(ScanError::truncated(pos, "string extends past the end of the input"));
+                };
+                if header.major == 3 && std::str::from_utf8(&self.data[content..end]).is_err() {
+                    self.violation(pos, path, "text string is not valid UTF-8")?;
+                    Ok(end)
+                } else if header.minimal {
+                    self.check_leaf(pos, end, path)
+                } else {
+                    Ok(end)
+                }
+            },
+            4 => {
+                let mut end = content;
+                for index in 0..header.value {
+                    end = self.scan(end, &format!("{}[{}]", path, index))?;
+                }
+                Ok(end)
+            },
+            5 => {
+                let mut end = content;
+                let mut previous_key: Option<(usize, usize)> = None;
+                for _ in 0..header.value {
+                    let key_start = end;
+                    end = self.scan(end, path)?;
+                    let key = &self.data[key_start..end];
+                    if let Some((previous_start, previous_end)) = previous_key {
+                        let previous = &self.data[previous_start..previous_end];
+                        if key == previous {
+                            self.violation(key_start, path, "duplicate map key")?;
+                        } else if key < previous {
+                            self.violation(key_start, path, "map keys are not in canonical order")?;
+                        }
+                    }
+                    previous_key = Some((key_start, end));
+                    let value_path = match CBOR::try_from_data(key) {
+                        Ok(key) => walk::key_path(path, &key),
+                        Err(_) => format!("{}[h'{}']", path, hex::encode(key)),
+                    };
+                    end = self.scan(end, &value_path)?;
+                }
+                Ok(end)
+            },
+            6 => self.scan(content, path),
+            _ => match header.additional {
+                25..=27 => {
+                    let Some(item) = self.data.get(pos..content) else {
+                        return Err(ScanError::truncated(pos, "the input ended inside a float"));
+                    };
+                    if let Some(problem) = float_problem(item) {
+                        self.violation(pos, path, problem)?;
+                    }
+                    Ok(content)
+                },
+                20..=22 => self.check_leaf(pos, content, path),
+                _ => {
+                    self.violation(pos, path, format!("unsupported simple value {}", header.value))?;
+                    Ok(content)
+                },
+            },
+        }
+    }
+}
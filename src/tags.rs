@@ -0,0 +1,57 @@
+//! Loading of user-defined tag names from a JSON or CSV file.
+
+use std::{io::Write, path::Path};
+
+use anyhow::{Result, anyhow, bail};
+use dcbor::prelude::*;
+
+use crate::read_text_file;
+
+/// Parses tag number/name pairs from either a JSON object mapping tag numbers
+/// to names (`{"40500": "my-type"}`) or CSV lines of the form `40500,my-type`.
+/// Blank CSV lines and lines starting with `#` are ignored.
+pub fn parse(text: &str) -> Result<Vec<(u64, String)>> {
+    if text.trim_start().starts_with('{') {
+        let object: serde_json::Map<String, serde_json::Value> = serde_json::from_str(text)?;
+        object.iter()
+            .map(|(number, name)| {
+                let name = name.as_str().ok_or_else(|| anyhow!("The name for tag {} must be a string", number))?;
+                Ok((parse_number(number)?, name.to_string()))
+            })
+            .collect()
+    } else {
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(index, line)| {
+                let Some((number, name)) = line.split_once(',') else {
+                    bail!("Line {} of the tags file must be of the form NUMBER,NAME", index + 1);
+                };
+                Ok((parse_number(number.trim())?, name.trim().to_string()))
+            })
+            .collect()
+    }
+}
+
+fn parse_number(text: &str) -> Result<u64> {
+    text.parse().map_err(|_| anyhow!("Invalid tag number: {}", text))
+}
+
+/// Registers the tags in the file at `path` with `store`. A tag that already
+/// has a different name keeps it, with a warning to `errors`, since the store
+/// does not allow a tag to be renamed.
+pub fn register(store: &mut TagsStore, path: &Path, errors: &mut dyn Write) -> Result<()> {
+    for (number, name) in parse(&read_text_file(path)?)? {
+        if name.is_empty() {
+            bail!("Tag {} has an empty name", number);
+        }
+        if let Some(existing) = store.tag_for_value(number).and_then(|tag| tag.name()) {
+            if existing != name {
+                writeln!(errors, "Warning: tag {} is already registered as \"{}\"; ignoring \"{}\"", number, existing, name)?;
+            }
+            continue;
+        }
+        store.insert(Tag::new(number, name.as_str()));
+    }
+    Ok(())
+}
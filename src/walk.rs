@@ -0,0 +1,79 @@
+//! Helpers for walking and rebuilding decoded dCBOR trees.
+
+use anyhow::Result;
+use dcbor::prelude::*;
+
+/// Returns the path of the value stored under `key` in the map at `path`, in
+/// the syntax of the select subcommand. Text keys that would read as unsigned
+/// integer keys after a `.` are quoted in brackets instead.
+pub fn key_path(path: &str, key: &CBOR) -> String {
+    match key.as_case() {
+        CBORCase::Text(text) if !text.is_empty() && text.chars().all(|c| c.is_alphanumeric() || c == '_') && text.parse::<u64>().is_err() => {
+            format!("{}.{}", path, text)
+        },
+        CBORCase::Unsigned(value) => format!("{}.{}", path, value),
+        _ => format!("{}[{}]", path, key),
+    }
+}
+
+/// Calls `visitor` on `cbor` and every value nested within it, along with its
+/// path from the root (`$`). Tagged values share the path of their content.
+pub fn visit<F>(cbor: &CBOR, path: &str, visitor: &mut F) -> Result<()>
+where
+    F: FnMut(&CBOR, &str) -> Result<()>
+{
+    visitor(cbor, path)?;
+    match cbor.as_case() {
+        CBORCase::Array(array) => {
+            for (index, item) in array.iter().enumerate() {
+                visit(item, &format!("{}[{}]", path, index), visitor)?;
+            }
+        },
+        CBORCase::Map(map) => {
+            for (key, value) in map.iter() {
+                visit(value, &key_path(path, key), visitor)?;
+            }
+        },
+        CBORCase::Tagged(_, item) => {
+            visit(item, path, visitor)?;
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+/// Returns the nesting depth of `cbor`: zero for a scalar, and one more than
+/// its deepest child for an array, map, or tagged value.
+pub fn depth(cbor: &CBOR) -> usize {
+    match cbor.as_case() {
+        CBORCase::Array(array) => 1 + array.iter().map(depth).max().unwrap_or(0),
+        CBORCase::Map(map) => 1 + map.iter().map(|(key, value)| depth(key).max(depth(value))).max().unwrap_or(0),
+        CBORCase::Tagged(_, item) => 1 + depth(item),
+        _ => 0,
+    }
+}
+
+/// Rebuilds `cbor` bottom-up, passing each rebuilt value (including map keys)
+/// through `f`.
+pub fn rewrite<F>(cbor: &CBOR, f: &F) -> CBOR
+where
+    F: Fn(CBOR) -> CBOR
+{
+    let rebuilt: CBOR = match cbor.as_case() {
+        CBORCase::Array(array) => {
+            array.iter().map(|item| rewrite(item, f)).collect::<Vec<CBOR>>().into()
+        },
+        CBORCase::Map(map) => {
+            let mut result = Map::new();
+            for (key, value) in map.iter() {
+                result.insert(rewrite(key, f), rewrite(value, f));
+            }
+            result.into()
+        },
+        CBORCase::Tagged(tag, item) => {
+            CBOR::to_tagged_value(tag.clone(), rewrite(item, f))
+        },
+        _ => cbor.clone(),
+    };
+    f(rebuilt)
+}
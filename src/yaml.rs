@@ -0,0 +1,73 @@
+//! Rendering of dCBOR as block-style YAML, using the same type mapping as the
+//! JSON output.
+
+use serde_json::Value;
+
+/// Renders `value` as a YAML document without a trailing newline.
+pub fn to_string(value: &Value) -> String {
+    let mut lines = vec!();
+    render(value, 0, &mut lines);
+    lines.join("\n")
+}
+
+/// Whether `value` is rendered on the same line as its key or list marker.
+fn is_inline(value: &Value) -> bool {
+    match value {
+        Value::Array(array) => array.is_empty(),
+        Value::Object(object) => object.is_empty(),
+        _ => true,
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(text) => string(text),
+        Value::Array(_) => "[]".to_string(),
+        Value::Object(_) => "{}".to_string(),
+        _ => value.to_string(),
+    }
+}
+
+/// Renders a string plain when YAML would read it back as the same string, and
+/// double-quoted (with JSON escapes, which YAML shares) otherwise.
+fn string(text: &str) -> String {
+    let reserved = ["true", "false", "null", "yes", "no", "on", "off", "y", "n", "~"];
+    let plain = text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && !text.ends_with(' ')
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | ' ' | '/'))
+        && !reserved.contains(&text.to_ascii_lowercase().as_str());
+    if plain {
+        text.to_string()
+    } else {
+        Value::String(text.to_string()).to_string()
+    }
+}
+
+fn render(value: &Value, depth: usize, lines: &mut Vec<String>) {
+    let indent = "  ".repeat(depth);
+    match value {
+        Value::Array(array) if !array.is_empty() => {
+            for item in array {
+                if is_inline(item) {
+                    lines.push(format!("{}- {}", indent, scalar(item)));
+                } else {
+                    // The item's first line shares the list marker's line.
+                    let start = lines.len();
+                    render(item, depth + 1, lines);
+                    lines[start] = format!("{}- {}", indent, lines[start].trim_start());
+                }
+            }
+        },
+        Value::Object(object) if !object.is_empty() => {
+            for (key, value) in object {
+                if is_inline(value) {
+                    lines.push(format!("{}{}: {}", indent, string(key), scalar(value)));
+                } else {
+                    lines.push(format!("{}{}:", indent, string(key)));
+                    render(value, depth + 1, lines);
+                }
+            }
+        },
+        _ => lines.push(format!("{}{}", indent, scalar(value))),
+    }
+}